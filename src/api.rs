@@ -1,11 +1,296 @@
-use reqwest::RequestBuilder;
-
-pub async fn send_and_text(req: RequestBuilder) -> Result<String, String> {
-    match req.send().await {
-        Ok(value) => match value.text().await {
-            Ok(text) => Ok(text),
-            Err(err) => Err(format!("Text error:\n{err}")),
-        },
-        Err(err) => Err(format!("Request error:\n{err}")),
+use core::time::Duration;
+use std::time::SystemTime;
+
+use actix_web::http::header::HttpDate;
+use actix_web::rt::time::sleep;
+use rand::Rng as _;
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Client, RequestBuilder, Response, StatusCode};
+
+use crate::error::{Error, Result};
+use crate::log;
+
+/// Sends the request, failing with [`Error::AuthRequired`] if Google rejected
+/// the access token.
+pub async fn send(req: RequestBuilder) -> Result<Response> {
+    let response = req
+        .send()
+        .await
+        .map_err(|err| Error::Network(format!("Request error:\n{err}")))?;
+    if response.status() == StatusCode::UNAUTHORIZED {
+        Err(Error::AuthRequired)
+    } else {
+        Ok(response)
+    }
+}
+
+/// Reads the body of a response.
+pub async fn text(response: Response) -> Result<String> {
+    response
+        .text()
+        .await
+        .map_err(|err| Error::Network(format!("Text error:\n{err}")))
+}
+
+/// Reads the body of a response that failed, and parses it as an error.
+pub async fn error_from(response: Response) -> Error {
+    let status = response.status();
+    match text(response).await {
+        Ok(body) => Error::from_response(status, &body),
+        Err(err) => err,
+    }
+}
+
+/// Reads the body of a successful response, or fails with the error parsed
+/// from a failed one, so that an error page is never taken for content.
+pub async fn success_text(response: Response) -> Result<String> {
+    if response.status().is_success() {
+        text(response).await
+    } else {
+        Err(error_from(response).await)
+    }
+}
+
+pub async fn send_and_text(req: RequestBuilder) -> Result<String> {
+    success_text(send(req).await?).await
+}
+
+/// Checks if a failure of Google is worth retrying: rate limits and transient
+/// server errors.
+const fn is_transient(status: StatusCode) -> bool {
+    matches!(
+        status,
+        StatusCode::TOO_MANY_REQUESTS
+            | StatusCode::INTERNAL_SERVER_ERROR
+            | StatusCode::BAD_GATEWAY
+            | StatusCode::SERVICE_UNAVAILABLE
+            | StatusCode::GATEWAY_TIMEOUT
+    )
+}
+
+/// Reads the delay asked by a `Retry-After` header, given either in seconds
+/// or as the HTTP date to wait for.
+fn retry_after(response: &Response) -> Option<Duration> {
+    let value = response.headers().get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = SystemTime::from(value.parse::<HttpDate>().ok()?);
+    Some(date.duration_since(SystemTime::now()).unwrap_or_default())
+}
+
+/// Base URLs of the Google services, that can point to a fake Google in tests.
+#[derive(Debug, Clone)]
+pub struct GoogleUrls {
+    /// Login pages, like `https://accounts.google.com`.
+    pub accounts: String,
+    /// Drive API and user info, like `https://www.googleapis.com`.
+    pub apis: String,
+    /// Docs API, like `https://docs.googleapis.com`.
+    pub docs: String,
+    /// Token exchange and revocation, like `https://oauth2.googleapis.com`.
+    pub oauth: String,
+}
+
+impl Default for GoogleUrls {
+    fn default() -> Self {
+        Self {
+            accounts: "https://accounts.google.com".to_owned(),
+            apis: "https://www.googleapis.com".to_owned(),
+            docs: "https://docs.googleapis.com".to_owned(),
+            oauth: "https://oauth2.googleapis.com".to_owned(),
+        }
+    }
+}
+
+/// Retries of the calls that Google failed transiently.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Attempts of a call, including the first one.
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled at each of the next ones.
+    pub base_delay: Duration,
+    /// Longest delay between two attempts. A call that Google asks to wait
+    /// longer for is not retried.
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 4,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(32),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Returns the delay before the given retry, counted from 1.
+    ///
+    /// The exponential backoff is jittered between half and all of its value,
+    /// so that concurrent calls don't retry in lockstep. A `Retry-After` asked
+    /// by Google is honoured, or the call given up if it exceeds the longest
+    /// delay.
+    fn to_delay(&self, retry: u32, retry_after: Option<Duration>) -> Option<Duration> {
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            .min(self.max_delay);
+        let jittered = backoff.mul_f64(rand::thread_rng().gen_range(0.5f64..=1.0f64));
+        match retry_after {
+            Some(after) if after > self.max_delay => None,
+            Some(after) => Some(after.max(jittered)),
+            None => Some(jittered),
+        }
+    }
+}
+
+/// Settings of the HTTP client shared by every call to Google.
+#[derive(Debug, Clone)]
+pub struct HttpConfig {
+    /// Time to establish a connection.
+    pub connect_timeout: Duration,
+    /// Idle connections kept open per host, to skip new TLS handshakes.
+    pub pool_max_idle_per_host: usize,
+    /// Time an idle connection is kept open.
+    pub pool_idle_timeout: Duration,
+    /// Time of a whole request, from connecting to reading the body.
+    pub timeout: Duration,
+    pub user_agent: String,
+    pub retry: RetryPolicy,
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        Self {
+            connect_timeout: Duration::from_secs(10),
+            pool_max_idle_per_host: 16,
+            pool_idle_timeout: Duration::from_secs(90),
+            timeout: Duration::from_secs(30),
+            user_agent: concat!("md-viewer/", env!("CARGO_PKG_VERSION")).to_owned(),
+            retry: RetryPolicy::default(),
+        }
+    }
+}
+
+impl HttpConfig {
+    fn to_client(&self) -> Result<Client> {
+        Client::builder()
+            .connect_timeout(self.connect_timeout)
+            .pool_max_idle_per_host(self.pool_max_idle_per_host)
+            .pool_idle_timeout(self.pool_idle_timeout)
+            .timeout(self.timeout)
+            .user_agent(&self.user_agent)
+            .build()
+            .map_err(|err| Error::Internal(format!("Failed to build the HTTP client:\n{err}")))
+    }
+}
+
+/// Endpoints of the Google APIs used by the server, with the client that
+/// calls them.
+#[derive(Debug, Clone)]
+pub struct GoogleApi {
+    client: Client,
+    retry: RetryPolicy,
+    urls: GoogleUrls,
+}
+
+impl GoogleApi {
+    pub fn new(http: &HttpConfig, urls: GoogleUrls) -> Result<Self> {
+        Ok(Self { client: http.to_client()?, retry: http.retry.clone(), urls })
+    }
+
+    /// Returns the client, to reuse its open connections.
+    pub const fn as_client(&self) -> &Client {
+        &self.client
+    }
+
+    /// Sends a request that can safely be repeated, retrying it with backoff
+    /// while Google is rate limiting or failing transiently.
+    ///
+    /// The last attempt is returned as is, for the caller to handle its
+    /// status.
+    pub async fn send_idempotent(&self, req: RequestBuilder) -> Result<Response> {
+        Ok(self.send_counting_attempts(req).await?.0)
+    }
+
+    /// Sends a request like [`Self::send_idempotent`], also returning the
+    /// number of attempts, so that the caller can tell an answer to a retry
+    /// from one to the first call.
+    pub async fn send_counting_attempts(&self, req: RequestBuilder) -> Result<(Response, u32)> {
+        let mut attempt = 1;
+        loop {
+            let Some(request) = (attempt < self.retry.max_attempts)
+                .then(|| req.try_clone())
+                .flatten()
+            else {
+                return Ok((send(req).await?, attempt));
+            };
+            let delay = match send(request).await {
+                Ok(response) if is_transient(response.status()) => {
+                    log!("Google answered {}.", response.status());
+                    let Some(delay) = self.retry.to_delay(attempt, retry_after(&response)) else {
+                        log!("Google asked to wait longer than {:?}.", self.retry.max_delay);
+                        return Ok((response, attempt));
+                    };
+                    delay
+                }
+                Err(Error::Network(err)) => {
+                    log!("{err}");
+                    self.retry
+                        .to_delay(attempt, None)
+                        .unwrap_or(self.retry.max_delay)
+                }
+                result => return result.map(|response| (response, attempt)),
+            };
+            log!("Retrying in {delay:?}, after attempt {attempt}.");
+            sleep(delay).await;
+            attempt = attempt.saturating_add(1);
+        }
+    }
+
+    pub async fn send_idempotent_and_text(&self, req: RequestBuilder) -> Result<String> {
+        success_text(self.send_idempotent(req).await?).await
+    }
+
+    pub fn auth_url(&self) -> String {
+        format!("{}/o/oauth2/auth", self.urls.accounts)
+    }
+
+    pub fn token_url(&self) -> String {
+        format!("{}/token", self.urls.oauth)
+    }
+
+    pub fn revoke_url(&self) -> String {
+        format!("{}/revoke", self.urls.oauth)
+    }
+
+    pub fn userinfo_url(&self) -> String {
+        format!("{}/oauth2/v2/userinfo", self.urls.apis)
+    }
+
+    pub fn files_url(&self) -> String {
+        format!("{}/drive/v3/files", self.urls.apis)
+    }
+
+    pub fn file_url(&self, id: &str) -> String {
+        format!("{}/drive/v3/files/{id}", self.urls.apis)
+    }
+
+    pub fn changes_url(&self) -> String {
+        format!("{}/drive/v3/changes", self.urls.apis)
+    }
+
+    pub fn upload_url(&self) -> String {
+        format!("{}/upload/drive/v3/files", self.urls.apis)
+    }
+
+    pub fn upload_file_url(&self, id: &str) -> String {
+        format!("{}/upload/drive/v3/files/{id}", self.urls.apis)
+    }
+
+    pub fn document_url(&self, id: &str) -> String {
+        format!("{}/v1/documents/{id}", self.urls.docs)
     }
 }
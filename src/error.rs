@@ -0,0 +1,200 @@
+use core::{fmt, result};
+
+use actix_web::http::{StatusCode, header};
+use actix_web::{HttpResponse, ResponseError};
+use serde::Deserialize;
+use serde_json::json;
+
+use crate::state::to_etag;
+
+pub type Result<T, E = Error> = result::Result<T, E>;
+
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// Google rejected the access token, or the browser isn't logged in.
+    AuthRequired,
+    /// The request of the client is invalid.
+    BadRequest(String),
+    /// The note was modified since the revision the client edited.
+    Conflict {
+        /// Current content of the note.
+        content: String,
+        /// Current revision of the note.
+        revision: String,
+    },
+    /// Google's response couldn't be deserialised to the expected type.
+    Deserialise(String),
+    /// Google answered with an error.
+    Google(GoogleError),
+    /// Internal failure of the server, like a poisoned lock.
+    Internal(String),
+    /// The request to Google couldn't be sent, or its response couldn't be
+    /// read.
+    Network(String),
+    /// A resource of the request doesn't exist.
+    NotFound(String),
+}
+
+impl Error {
+    /// Builds the error from a failed response, parsing Google's error
+    /// envelope if possible.
+    pub fn from_response(status: reqwest::StatusCode, body: &str) -> Self {
+        match status {
+            reqwest::StatusCode::UNAUTHORIZED => Self::AuthRequired,
+            reqwest::StatusCode::NOT_FOUND =>
+                Self::NotFound(GoogleError::from_body(status.as_u16(), body).message),
+            _ => Self::Google(GoogleError::from_body(status.as_u16(), body)),
+        }
+    }
+
+    pub fn deserialise<E: fmt::Display>(err: &E, objective: &str, data: &str) -> Self {
+        Self::Deserialise(format!(
+            "Failed to deserialise response to {objective}:\n{err}\n\nData:\n{data}"
+        ))
+    }
+
+    pub const fn is_unauthorised(&self) -> bool {
+        matches!(self, Self::AuthRequired)
+    }
+
+    fn as_google_status(&self) -> Option<&str> {
+        match self {
+            Self::Google(err) => err.status.as_deref(),
+            Self::AuthRequired
+            | Self::BadRequest(_)
+            | Self::Conflict { .. }
+            | Self::Deserialise(_)
+            | Self::Internal(_)
+            | Self::Network(_)
+            | Self::NotFound(_) => None,
+        }
+    }
+
+    const fn kind(&self) -> &'static str {
+        match self {
+            Self::AuthRequired => "auth_required",
+            Self::BadRequest(_) => "bad_request",
+            Self::Conflict { .. } => "conflict",
+            Self::Deserialise(_) => "deserialise",
+            Self::Google(_) => "google",
+            Self::Internal(_) => "internal",
+            Self::Network(_) => "network",
+            Self::NotFound(_) => "not_found",
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::AuthRequired => "Authentication required.".fmt(f),
+            Self::Conflict { .. } => "The note was modified since it was loaded.".fmt(f),
+            Self::Google(err) => err.fmt(f),
+            Self::BadRequest(msg)
+            | Self::Deserialise(msg)
+            | Self::Internal(msg)
+            | Self::Network(msg)
+            | Self::NotFound(msg) => msg.fmt(f),
+        }
+    }
+}
+
+#[expect(clippy::missing_trait_methods, reason = "private method of actix")]
+impl ResponseError for Error {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Self::AuthRequired => StatusCode::UNAUTHORIZED,
+            Self::BadRequest(_) => StatusCode::BAD_REQUEST,
+            Self::Conflict { .. } => StatusCode::CONFLICT,
+            Self::Google(err) => err.to_status_code(),
+            Self::NotFound(_) => StatusCode::NOT_FOUND,
+            Self::Deserialise(_) | Self::Network(_) => StatusCode::BAD_GATEWAY,
+            Self::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let status = self.status_code();
+        let mut error = json!({
+            "code": status.as_u16(),
+            "kind": self.kind(),
+            "message": self.to_string(),
+            "status": self.as_google_status(),
+        });
+        let mut response = HttpResponse::build(status);
+        if let Self::Conflict { content, revision } = self
+            && let Some(fields) = error.as_object_mut()
+        {
+            fields.insert("content".to_owned(), content.as_str().into());
+            fields.insert("revision".to_owned(), revision.as_str().into());
+            response.insert_header((header::ETAG, to_etag(revision)));
+        }
+        response.json(json!({ "error": error }))
+    }
+}
+
+/// Error returned by a Google API.
+#[derive(Debug)]
+pub struct GoogleError {
+    /// HTTP status code of Google's response.
+    code: u16,
+    message: String,
+    /// Canonical error name, like `NOT_FOUND` or `invalid_grant`.
+    status: Option<String>,
+}
+
+/// Envelope of the errors of the Drive and Docs APIs.
+#[derive(Deserialize)]
+struct ApiErrorEnvelope {
+    error: ApiErrorBody,
+}
+
+#[derive(Deserialize)]
+struct ApiErrorBody {
+    message: String,
+    status: Option<String>,
+}
+
+/// Errors of the OAuth endpoints.
+#[derive(Deserialize)]
+struct OAuthErrorBody {
+    error: String,
+    error_description: Option<String>,
+}
+
+impl GoogleError {
+    fn from_body(code: u16, body: &str) -> Self {
+        if let Ok(envelope) = serde_json::from_str::<ApiErrorEnvelope>(body) {
+            Self { code, message: envelope.error.message, status: envelope.error.status }
+        } else if let Ok(oauth) = serde_json::from_str::<OAuthErrorBody>(body) {
+            Self {
+                code,
+                message: oauth
+                    .error_description
+                    .unwrap_or_else(|| oauth.error.clone()),
+                status: Some(oauth.error),
+            }
+        } else {
+            Self { code, message: body.to_owned(), status: None }
+        }
+    }
+
+    /// Status to answer with: client errors are forwarded, server errors are
+    /// reported as a bad gateway.
+    fn to_status_code(&self) -> StatusCode {
+        StatusCode::from_u16(self.code)
+            .ok()
+            .filter(StatusCode::is_client_error)
+            .unwrap_or(StatusCode::BAD_GATEWAY)
+    }
+}
+
+impl fmt::Display for GoogleError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.status {
+            Some(status) => write!(f, "Google error {} ({status}): {}", self.code, self.message),
+            None => write!(f, "Google error {}: {}", self.code, self.message),
+        }
+    }
+}
@@ -15,16 +15,30 @@ impl GoogleAuthCredentials {
     pub fn as_params<'code, 'db: 'code>(
         &'db self,
         code: &'code str,
-    ) -> [(&'code str, &'code str); 5] {
+        verifier: &'code str,
+    ) -> [(&'code str, &'code str); 6] {
         [
             ("client_id", self.id.as_str()),
             ("client_secret", self.secret.as_str()),
             ("code", code),
+            ("code_verifier", verifier),
             ("redirect_uri", self.redirect_uri.as_str()),
             ("grant_type", "authorization_code"),
         ]
     }
 
+    pub const fn as_refresh_params<'token, 'db: 'token>(
+        &'db self,
+        refresh_token: &'token str,
+    ) -> [(&'token str, &'token str); 4] {
+        [
+            ("client_id", self.id.as_str()),
+            ("client_secret", self.secret.as_str()),
+            ("refresh_token", refresh_token),
+            ("grant_type", "refresh_token"),
+        ]
+    }
+
     pub const fn new(id: String, redirect_uri: String, secret: String) -> Self {
         Self { id, redirect_uri, secret }
     }
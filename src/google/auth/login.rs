@@ -1,29 +1,68 @@
-use actix_web::web::{self, Redirect};
-use actix_web::{HttpRequest, HttpResponse, http};
-use reqwest::Client;
+use core::time::Duration;
+use std::time::Instant;
+
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse, web};
+use base64::Engine as _;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
 use serde::{Deserialize, Serialize};
+use sha2::{Digest as _, Sha256};
 
-use crate::api::send_and_text;
-use crate::state::ok_or_internal;
-use crate::{AppData, token, unwrap_return, unwrap_return_internal};
+use crate::api::{GoogleApi, send_and_text};
+use crate::error::{Error, Result};
+use crate::google::auth::credentials::GoogleAuthCredentials;
+use crate::session::random_token;
+use crate::state::ok_or_error;
+use crate::view::message_page;
+use crate::{AppData, unwrap_return_error, with_token};
 
 const SCOPE: &str = "email%20profile%20https://www.googleapis.com/auth/drive%20openid";
 
+/// Access tokens are refreshed when they expire in less than this duration.
+const EXPIRY_MARGIN: Duration = Duration::from_mins(1);
+
+/// Secrets of a login in progress, checked when Google redirects back to the
+/// callback.
+#[derive(Debug)]
+pub struct PendingLogin {
+    state: Box<str>,
+    verifier: Box<str>,
+}
+
+impl PendingLogin {
+    fn new() -> Self {
+        Self { state: random_token(), verifier: random_token() }
+    }
+
+    /// PKCE challenge for the code verifier, with the `S256` method.
+    fn to_challenge(&self) -> String {
+        URL_SAFE_NO_PAD.encode(Sha256::digest(self.verifier.as_bytes()))
+    }
+}
+
 #[actix_web::get("/login")]
-async fn google_login(data: AppData) -> Redirect {
-    web::Redirect::to(format!(
-        "https://accounts.google.com/o/oauth2/auth?client_id={client_id}&redirect_uri={redirect_uri}&response_type=code&scope={scope}&access_type=offline",
+async fn google_login(data: AppData, req: HttpRequest) -> HttpResponse {
+    let pending_login = PendingLogin::new();
+    let url = format!(
+        "{auth_url}?client_id={client_id}&redirect_uri={redirect_uri}&response_type=code&scope={scope}&access_type=offline&prompt=consent&state={state}&code_challenge={challenge}&code_challenge_method=S256",
+        auth_url = data.as_google().auth_url(),
         client_id = data.as_credentials().as_id(),
         redirect_uri = data.as_credentials().as_redirect_uri(),
-        scope = SCOPE
-    ))
-    .permanent()
-    .using_status_code(http::StatusCode::FOUND)
+        scope = SCOPE,
+        state = pending_login.state,
+        challenge = pending_login.to_challenge(),
+    );
+    HttpResponse::Found()
+        .cookie(unwrap_return_error!(data.start_login(&req, pending_login)))
+        .append_header(("Location", url))
+        .finish()
 }
 
 #[derive(Deserialize)]
 struct CallBack {
-    code: String,
+    code: Option<String>,
+    error: Option<String>,
+    state: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug)]
@@ -31,6 +70,9 @@ pub struct ClientOAuthData {
     access_token: String,
     expires_in: u32,
     id_token: String,
+    #[serde(skip, default = "Instant::now")]
+    issued_at: Instant,
+    refresh_token: Option<String>,
     scope: String,
     token_type: String,
 }
@@ -39,40 +81,127 @@ impl ClientOAuthData {
     pub fn as_token(&self) -> &str {
         &self.access_token
     }
+
+    pub fn as_refresh_token(&self) -> Option<&str> {
+        self.refresh_token.as_deref()
+    }
+
+    /// Checks if the access token has expired or is about to.
+    pub fn is_expiring(&self) -> bool {
+        self.issued_at.elapsed().saturating_add(EXPIRY_MARGIN)
+            >= Duration::from_secs(self.expires_in.into())
+    }
+
+    pub fn refresh(&mut self, refreshed: RefreshedToken) {
+        self.access_token = refreshed.access_token;
+        self.expires_in = refreshed.expires_in;
+        self.issued_at = Instant::now();
+        if let Some(refresh_token) = refreshed.refresh_token {
+            self.refresh_token = Some(refresh_token);
+        }
+    }
 }
 
-#[actix_web::get("/callback/google")]
-async fn google_callback(query: web::Query<CallBack>, data: AppData) -> HttpResponse {
-    HttpResponse::InternalServerError().body(
-        match send_and_text(
-            Client::new()
-                .post("https://oauth2.googleapis.com/token")
-                .form(&data.as_credentials().as_params(&query.code)),
+/// Response of Google to a `refresh_token` grant.
+#[derive(Deserialize)]
+pub struct RefreshedToken {
+    access_token: String,
+    expires_in: u32,
+    refresh_token: Option<String>,
+}
+
+pub async fn refresh_access_token(
+    google: &GoogleApi,
+    credentials: &GoogleAuthCredentials,
+    refresh_token: &str,
+) -> Result<RefreshedToken> {
+    google
+        .send_idempotent_and_text(
+            google
+                .as_client()
+                .post(google.token_url())
+                .form(&credentials.as_refresh_params(refresh_token)),
         )
         .await
-        {
-            Ok(text) => match serde_json::from_str(&text) {
-                Ok(new_client_data) => {
-                    unwrap_return!(data.set_client_data(new_client_data));
-                    return HttpResponse::Found()
-                        .append_header(("Location", unwrap_return_internal!(data.take_callback())))
-                        .finish();
-                }
-                Err(err) => format!("Failed to parse response:\n{err}\nResponse:\n{text}"),
-            },
-            Err(err) => err,
-        },
+        .and_then(|text| {
+            serde_json::from_str(&text)
+                .map_err(|err| Error::deserialise(&err, "RefreshedToken", &text))
+        })
+}
+
+#[actix_web::get("/callback/google")]
+async fn google_callback(
+    query: web::Query<CallBack>,
+    data: AppData,
+    req: HttpRequest,
+) -> HttpResponse {
+    // Taken even if the callback fails, so that a state can only be used once.
+    let Some(pending_login) = unwrap_return_error!(data.take_pending_login(&req))
+        .filter(|pending| query.state.as_deref() == Some(&*pending.state))
+    else {
+        return login_error_page(
+            StatusCode::BAD_REQUEST,
+            "The login request is invalid or has expired. Please start again from this browser.",
+        );
+    };
+    if let Some(err) = &query.error {
+        return login_error_page(
+            StatusCode::FORBIDDEN,
+            &format!("Google refused the login: {err}"),
+        );
+    }
+    let Some(code) = &query.code else {
+        return login_error_page(
+            StatusCode::BAD_REQUEST,
+            "Google didn't send an authorisation code.",
+        );
+    };
+    let google = data.as_google();
+    match send_and_text(
+        google.as_client().post(google.token_url()).form(
+            &data
+                .as_credentials()
+                .as_params(code, &pending_login.verifier),
+        ),
     )
+    .await
+    {
+        Ok(text) => match serde_json::from_str(&text) {
+            Ok(new_client_data) => {
+                let (callback, cookie) = unwrap_return_error!(data.log_in(&req, new_client_data));
+                HttpResponse::Found()
+                    .cookie(cookie)
+                    .append_header(("Location", callback))
+                    .finish()
+            }
+            Err(err) => login_error_page(
+                StatusCode::BAD_GATEWAY,
+                &Error::deserialise(&err, "ClientOAuthData", &text).to_string(),
+            ),
+        },
+        Err(err @ Error::Google(_)) => login_error_page(
+            StatusCode::BAD_GATEWAY,
+            &format!("Google refused to issue an access token:\n{err}"),
+        ),
+        Err(err) => login_error_page(StatusCode::BAD_GATEWAY, &err.to_string()),
+    }
+}
+
+fn login_error_page(status: StatusCode, message: &str) -> HttpResponse {
+    message_page(status, "Login failed", message, ("/auth/login", "Try again"))
 }
 
 #[actix_web::get("/info")]
 async fn profile_info(data: AppData, req: HttpRequest) -> HttpResponse {
-    ok_or_internal(
-        send_and_text(
-            Client::new()
-                .get("https://www.googleapis.com/oauth2/v2/userinfo")
-                .bearer_auth(token!(data, req)),
-        )
-        .await,
-    )
+    let google = data.as_google();
+    ok_or_error(with_token!(data, req, |token| {
+        google
+            .send_idempotent_and_text(
+                google
+                    .as_client()
+                    .get(google.userinfo_url())
+                    .bearer_auth(token),
+            )
+            .await
+    }))
 }
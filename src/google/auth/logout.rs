@@ -0,0 +1,88 @@
+use actix_web::http::StatusCode;
+use actix_web::{HttpRequest, HttpResponse};
+
+use crate::api::{GoogleApi, error_from};
+use crate::error::{Error, Result};
+use crate::view::message_page;
+use crate::{AppData, log, unwrap_return_error};
+
+#[actix_web::post("/logout")]
+async fn google_logout(data: AppData, req: HttpRequest) -> HttpResponse {
+    let (_, cookie) = unwrap_return_error!(data.log_out(&req));
+    let mut response = message_page(
+        StatusCode::OK,
+        "Logged out",
+        "You are now logged out of this browser.",
+        ("/auth/login", "Log in"),
+    );
+    unwrap_return_error!(
+        response
+            .add_cookie(&cookie)
+            .map_err(|err| Error::Internal(err.to_string()))
+    );
+    response
+}
+
+#[actix_web::post("/revoke")]
+async fn google_revoke(data: AppData, req: HttpRequest) -> HttpResponse {
+    let (old_client_data, cookie) = unwrap_return_error!(data.log_out(&req));
+    let mut response = match old_client_data {
+        Some(client_data) => match revoke_token(
+            data.as_google(),
+            client_data
+                .as_refresh_token()
+                .unwrap_or_else(|| client_data.as_token()),
+        )
+        .await
+        {
+            Ok(()) => message_page(
+                StatusCode::OK,
+                "Access revoked",
+                "You are logged out, and md-viewer can no longer access your Google account.",
+                ("/auth/login", "Log in"),
+            ),
+            Err(err) => {
+                log!("Failed to revoke token:\n{err}");
+                message_page(
+                    StatusCode::BAD_GATEWAY,
+                    "Revocation failed",
+                    &format!(
+                        "You are logged out of this browser, but Google failed to revoke \
+                         md-viewer's access: {err}\nYou can still remove it from your Google \
+                         account's security settings."
+                    ),
+                    ("https://myaccount.google.com/permissions", "Google account permissions"),
+                )
+            }
+        },
+        None => message_page(
+            StatusCode::OK,
+            "Not logged in",
+            "This browser isn't logged in, so there is no access to revoke.",
+            ("/auth/login", "Log in"),
+        ),
+    };
+    unwrap_return_error!(
+        response
+            .add_cookie(&cookie)
+            .map_err(|err| Error::Internal(err.to_string()))
+    );
+    response
+}
+
+/// Revokes a token, and with it the whole access granted to md-viewer.
+async fn revoke_token(google: &GoogleApi, token: &str) -> Result<()> {
+    let response = google
+        .send_idempotent(
+            google
+                .as_client()
+                .post(google.revoke_url())
+                .form(&[("token", token)]),
+        )
+        .await?;
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(error_from(response).await)
+    }
+}
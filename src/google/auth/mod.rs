@@ -1,11 +1,15 @@
 pub mod credentials;
 pub mod login;
+mod logout;
 
 use actix_web::web;
 use login::{google_callback, google_login, profile_info};
+use logout::{google_logout, google_revoke};
 
 pub fn auth_config(cfg: &mut web::ServiceConfig) {
     cfg.service(google_callback)
         .service(google_login)
-        .service(profile_info);
+        .service(profile_info)
+        .service(google_logout)
+        .service(google_revoke);
 }
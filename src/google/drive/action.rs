@@ -1,44 +1,130 @@
-use actix_web::{HttpRequest, HttpResponse, web};
+use actix_web::http::header;
+use actix_web::{HttpRequest, HttpResponse, ResponseError as _, web};
 
 pub fn config(cfg: &mut web::ServiceConfig) {
     cfg //
+        .service(create_folder)
         .service(create_name)
+        .service(delete)
         .service(get_content)
         .service(get_doc_len)
-        .service(set_content);
+        .service(get_metadata)
+        .service(move_note)
+        .service(rename)
+        .service(restore)
+        .service(set_content)
+        .service(trash);
 }
 
-use reqwest::Client;
+use serde::Deserialize;
 use serde_json::{Value, json};
 
-use crate::state::{AppData, ok_or_internal};
-use crate::{log, token, unwrap_return_internal};
+use super::diff::DocumentText;
+use super::interface::{DriveFile, NoteFormat, download_file, get_file_state, update_file_media};
+use crate::api::{GoogleApi, error_from, send, success_text, text};
+use crate::error::{Error, Result};
+use crate::state::{AppData, ok_or_error, to_etag};
+use crate::storage::{Note, NoteMetadata, NoteStorage as _};
+use crate::{log, with_storage};
+
+#[derive(Deserialize)]
+struct CreateQuery {
+    /// Storage of the note, overriding the `NOTE_FORMAT` setting.
+    format: Option<NoteFormat>,
+    /// Subfolder of the app folder to create the note in.
+    folder: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct MetadataQuery {
+    /// Fields of the note to return, in Drive's syntax (e.g. `id,name`).
+    fields: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct FolderQuery {
+    /// Folder to create the subfolder in, the app folder if none is given.
+    parent: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct MoveQuery {
+    /// Folder to move the note to, the app folder if none is given.
+    to: Option<String>,
+}
 
 #[actix_web::get("/create/{name}")]
-async fn create_name(data: AppData, req: HttpRequest, path: web::Path<(String,)>) -> HttpResponse {
-    let token = token!(data, req);
-    ok_or_internal(
-        create_file_with_name(
-            &path.into_inner().0,
-            &unwrap_return_internal!(data.as_drive().app_folder_id(token).await),
-            token,
-        )
-        .await,
-    )
+async fn create_name(
+    data: AppData,
+    req: HttpRequest,
+    path: web::Path<(String,)>,
+    query: web::Query<CreateQuery>,
+) -> HttpResponse {
+    let name = path.into_inner().0;
+    let format = query.format.unwrap_or_else(|| data.as_note_format());
+    ok_or_error(with_storage!(data, req, |storage| {
+        match &query.folder {
+            Some(folder) =>
+                storage
+                    .as_drive()?
+                    .create_in(&name, format, Some(folder))
+                    .await,
+            None => storage.create(&name, format).await,
+        }
+        .map(|metadata| metadata.id)
+    }))
+}
+
+#[actix_web::post("/create-folder/{name}")]
+async fn create_folder(
+    data: AppData,
+    req: HttpRequest,
+    path: web::Path<(String,)>,
+    query: web::Query<FolderQuery>,
+) -> HttpResponse {
+    let name = path.into_inner().0;
+    metadata_or_error(with_storage!(data, req, |storage| {
+        storage
+            .as_drive()?
+            .create_folder(&name, query.parent.as_deref())
+            .await
+    }))
+}
+
+/// Moves a note or a subfolder to another folder of the app folder.
+#[actix_web::post("/move/{id}")]
+async fn move_note(
+    data: AppData,
+    req: HttpRequest,
+    path: web::Path<(String,)>,
+    query: web::Query<MoveQuery>,
+) -> HttpResponse {
+    let id = path.into_inner().0;
+    metadata_or_error(with_storage!(data, req, |storage| {
+        storage.as_drive()?.move_to(&id, query.to.as_deref()).await
+    }))
 }
 
 #[actix_web::get("/get-doc-len/{id}")]
 async fn get_doc_len(data: AppData, req: HttpRequest, path: web::Path<(String,)>) -> HttpResponse {
-    ok_or_internal(
-        get_document_length(&path.into_inner().0, token!(data, req))
+    let id = path.into_inner().0;
+    ok_or_error(with_storage!(data, req, |storage| {
+        let drive = storage.as_drive()?;
+        get_document_state(drive.as_google(), &id, drive.as_token())
             .await
-            .map(|len| len.to_string()),
-    )
+            .map(|document| document.end_index.to_string())
+    }))
 }
 
 #[actix_web::get("/get-content/{id}")]
 async fn get_content(data: AppData, req: HttpRequest, path: web::Path<(String,)>) -> HttpResponse {
-    ok_or_internal(get_file_content(&path.into_inner().0, token!(data, req)).await)
+    let id = path.into_inner().0;
+    match with_storage!(data, req, |storage| storage.read(&id).await) {
+        Ok(note) => HttpResponse::Ok()
+            .insert_header((header::ETAG, to_etag(&note.revision)))
+            .body(note.content),
+        Err(err) => err.error_response(),
+    }
 }
 
 #[actix_web::post("/set-content/{id}")]
@@ -48,167 +134,275 @@ async fn set_content(
     content: String,
     path: web::Path<(String,)>,
 ) -> HttpResponse {
-    ok_or_internal(set_file_content(&path.into_inner().0, &content, token!(data, req)).await)
+    let id = path.into_inner().0;
+    let expected = req
+        .headers()
+        .get(header::IF_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(from_if_match);
+    match with_storage!(data, req, |storage| {
+        let revision = storage.write(&id, &content, expected).await?;
+        if let (Some(index), Ok(drive)) = (data.as_search_index(), storage.as_drive()) {
+            index.update_content(&drive.app_folder_id().await?, &id, &content);
+        }
+        Ok(revision)
+    }) {
+        Ok(revision) => HttpResponse::Ok()
+            .insert_header((header::ETAG, to_etag(&revision)))
+            .body(format!("File updated with content {content}")),
+        Err(err) => err.error_response(),
+    }
+}
+
+#[actix_web::get("/metadata/{id}")]
+async fn get_metadata(
+    data: AppData,
+    req: HttpRequest,
+    path: web::Path<(String,)>,
+    query: web::Query<MetadataQuery>,
+) -> HttpResponse {
+    let id = path.into_inner().0;
+    metadata_or_error(with_storage!(data, req, |storage| {
+        storage.metadata(&id, query.fields.as_deref()).await
+    }))
+}
+
+/// Renames a note to the name sent as body.
+#[actix_web::post("/rename/{id}")]
+async fn rename(
+    data: AppData,
+    req: HttpRequest,
+    name: String,
+    path: web::Path<(String,)>,
+) -> HttpResponse {
+    let id = path.into_inner().0;
+    metadata_or_error(with_storage!(data, req, |storage| storage.rename(&id, &name).await))
 }
 
-async fn create_file_with_name(name: &str, folder_id: &str, token: &str) -> Result<String, String> {
-    serde_json::from_str::<Value>(
-        &Client::new()
-            .post("https://www.googleapis.com/drive/v3/files")
+/// Moves a note to the trash.
+#[actix_web::delete("/{id}")]
+async fn trash(data: AppData, req: HttpRequest, path: web::Path<(String,)>) -> HttpResponse {
+    let id = path.into_inner().0;
+    metadata_or_error(with_storage!(data, req, |storage| storage.trash(&id).await))
+}
+
+#[actix_web::post("/{id}/restore")]
+async fn restore(data: AppData, req: HttpRequest, path: web::Path<(String,)>) -> HttpResponse {
+    let id = path.into_inner().0;
+    metadata_or_error(with_storage!(data, req, |storage| storage.restore(&id).await))
+}
+
+/// Deletes a note permanently, whether it is trashed or not.
+#[actix_web::post("/delete/{id}")]
+async fn delete(data: AppData, req: HttpRequest, path: web::Path<(String,)>) -> HttpResponse {
+    let id = path.into_inner().0;
+    ok_or_error(with_storage!(data, req, |storage| {
+        storage
+            .delete(&id)
+            .await
+            .map(|()| format!("File {id} deleted."))
+    }))
+}
+
+fn metadata_or_error(value: Result<NoteMetadata>) -> HttpResponse {
+    match value {
+        Ok(metadata) => HttpResponse::Ok().json(metadata),
+        Err(err) => err.error_response(),
+    }
+}
+
+/// Reads the revision of an `If-Match` header, `*` and empty values matching
+/// any revision.
+fn from_if_match(value: &str) -> Option<&str> {
+    let tag = value.trim();
+    let revision = tag.trim_start_matches("W/").trim_matches('"');
+    (tag != "*" && !revision.is_empty()).then_some(revision)
+}
+
+pub async fn create_file_with_name(
+    google: &GoogleApi,
+    name: &str,
+    folder_id: &str,
+    token: &str,
+) -> Result<DriveFile> {
+    let response = send(
+        google
+            .as_client()
+            .post(google.files_url())
             .bearer_auth(token)
-            .header("Content-Type", "application/json")
             .json(&json!({
                 "name": name,
                 "parents": [folder_id],
                 "mimeType": "application/vnd.google-apps.document"
-            }))
-            .send()
-            .await
-            .map_err(|err| err.to_string())?
-            .text()
-            .await
-            .map_err(|err| err.to_string())?,
+            })),
     )
-    .map_err(|err| err.to_string())?
-    .get("id")
-    .and_then(|id| id.as_str())
-    .map(str::to_owned)
-    .ok_or_else(|| "Failed to get file ID".to_owned())
-}
-
-async fn get_file_content(id: &str, token: &str) -> Result<String, String> {
-    Client::new()
-        .get(format!(
-            "https://www.googleapis.com/drive/v3/files/{id}/export?mimeType=text/plain"
-        ))
-        .bearer_auth(token)
-        .send()
-        .await
-        .map_err(|err| err.to_string())?
-        .text()
-        .await
-        .map_err(|err| err.to_string())
+    .await?;
+    let body = success_text(response).await?;
+    serde_json::from_str(&body).map_err(|err| Error::deserialise(&err, "DriveFile", &body))
 }
 
-async fn get_document_length(id: &str, token: &str) -> Result<i32, String> {
-    let response = Client::new()
-        .get(format!("https://docs.googleapis.com/v1/documents/{id}"))
-        .bearer_auth(token)
-        .send()
-        .await
-        .map_err(|err| err.to_string())?;
-
-    if response.status().is_success() {
-        response
-            .json::<Value>()
-            .await
-            .map_err(|err| format!("Invalid response:\n{err}"))?
-            .get("body")
-            .and_then(|value| value.get("content"))
-            .and_then(|value| value.as_array())
-            .and_then(|value| value.last())
-            .and_then(|value| value.get("endIndex"))
-            .and_then(Value::as_i64)
-            .and_then(|value| i32::try_from(value).ok())
-            .map_or_else(|| Err("Failed to find document end index.".to_owned()), Ok)
+/// Returns the content of a note with its revision.
+///
+/// The revision of a Google Doc is read before its content, so that a
+/// concurrent edit makes the next save conflict instead of being lost.
+pub async fn get_note(google: &GoogleApi, id: &str, token: &str) -> Result<Note> {
+    let state = get_file_state(google, token, id).await?;
+    if state.is_document() {
+        let revision = get_document_state(google, id, token).await?.revision_id;
+        Ok(Note { content: export_document(google, id, token).await?, revision })
     } else {
-        Err(format!(
-            "Failed to retrieve document info: {}",
-            response
-                .text()
-                .await
-                .map_err(|err| format!("Response has invalid text:\n{err}"))?
-        ))
+        Ok(Note {
+            content: download_file(google, token, id).await?,
+            revision: state.to_revision()?,
+        })
     }
 }
 
-async fn set_file_content(id: &str, content: &str, token: &str) -> Result<String, String> {
-    let end = get_document_length(id, token).await?.saturating_sub(1);
-    log!("Updating file {id} (current len = {}) with {content}.", end.saturating_sub(1));
-
-    let request_body = if end <= 1i32 {
-        json!({
-            "requests": [
-                {
-                    "insertText": {
-                        "text": content,
-                        "location":  {
-                            // "segmentId" empty for body
-                            // "tabId" empty for "singular tab"?
-                            "index": 1i32,
-
-                        }
-                    }
-                }
-            ]
-        })
-    } else if content.is_empty() {
-        json!({
-            "requests": [
-                {
-                    "deleteContentRange": {
-                        "range": {
-                            // "segmentId"
-                            // "tabId"
-                            "startIndex": 1i32,
-                            "endIndex": end,
-                        }
-                    },
-                }
-            ]
-        })
-    } else {
-        json!({
-            "requests": [
-                {
-                    "deleteContentRange": {
-                        "range": {
-                            // "segmentId"
-                            // "tabId"
-                            "startIndex": 1i32,
-                            "endIndex": end,
-                        }
-                    },
-                },
-                {
-                    "insertText": {
-                        "text": content,
-                        "location":  {
-                            // "segmentId"
-                            // "tabId"
-                            "index": 1i32,
-
-                        }
-                    }
-                }
-            ]
-        })
-    };
-
-    let response = Client::new()
-        .post(format!("https://docs.googleapis.com/v1/documents/{id}:batchUpdate"))
-        .bearer_auth(token)
-        .header("Content-Type", "application/json")
-        .json(&request_body)
-        .send()
+/// Exports a Google Doc as plain text, without the BOM and with the `\n` line
+/// ends of its text runs, so that saving it back unchanged is a no-op.
+async fn export_document(google: &GoogleApi, id: &str, token: &str) -> Result<String> {
+    let response = google
+        .send_idempotent(
+            google
+                .as_client()
+                .get(format!("{}/export", google.file_url(id)))
+                .query(&[("mimeType", "text/plain")])
+                .bearer_auth(token),
+        )
+        .await?;
+    let text = success_text(response).await?;
+    Ok(text.trim_start_matches('\u{feff}').replace("\r\n", "\n"))
+}
+
+/// End index, revision and text of a Google Doc.
+struct DocumentState {
+    end_index: i32,
+    revision_id: String,
+    text: DocumentText,
+}
+
+async fn get_document_state(google: &GoogleApi, id: &str, token: &str) -> Result<DocumentState> {
+    let response = google
+        .send_idempotent(
+            google
+                .as_client()
+                .get(google.document_url(id))
+                .bearer_auth(token),
+        )
+        .await?;
+
+    if !response.status().is_success() {
+        return Err(error_from(response).await);
+    }
+    let document = response
+        .json::<Value>()
         .await
-        .map_err(|err| err.to_string())?;
-
-    if response.status().is_success() {
-        Ok(format!(
-            "File updated with content {content}\nResponse:\n{}",
-            response
-                .text()
-                .await
-                .map_err(|err| format!("Failed to get text from response:\n{err}"))?
-        ))
-    } else {
-        Err(format!(
-            "Failed to update content:\n{}",
-            response
-                .text()
-                .await
-                .map_err(|err| format!("Failed to get text from response:\n{err}"))?
-        ))
+        .map_err(|err| Error::Deserialise(format!("Invalid response:\n{err}")))?;
+    let end_index = document
+        .get("body")
+        .and_then(|value| value.get("content"))
+        .and_then(|value| value.as_array())
+        .and_then(|value| value.last())
+        .and_then(|value| value.get("endIndex"))
+        .and_then(Value::as_i64)
+        .and_then(|value| i32::try_from(value).ok())
+        .ok_or_else(|| Error::Deserialise("Failed to find document end index.".to_owned()))?;
+    let revision_id = document
+        .get("revisionId")
+        .and_then(Value::as_str)
+        .map(str::to_owned)
+        .ok_or_else(|| Error::Deserialise("Failed to find document revision.".to_owned()))?;
+    let text = DocumentText::from_document(&document)
+        .ok_or_else(|| Error::Deserialise("Failed to read document text.".to_owned()))?;
+    Ok(DocumentState { end_index, revision_id, text })
+}
+
+/// Replaces the content of a note, byte for byte if it isn't a Google Doc,
+/// and returns its new revision.
+///
+/// If an expected revision is given and the note moved on since, it is left
+/// untouched and [`Error::Conflict`] is returned with its current content.
+///
+/// The revision of a plain file is checked before it is uploaded, as Drive's
+/// media uploads can't be made conditional: a save made by someone else
+/// between the check and the upload is overwritten without conflict.
+pub async fn set_file_content(
+    google: &GoogleApi,
+    id: &str,
+    content: &str,
+    expected: Option<&str>,
+    token: &str,
+) -> Result<String> {
+    let state = get_file_state(google, token, id).await?;
+    if !state.is_document() {
+        let revision = state.to_revision()?;
+        if expected.is_some_and(|expected_revision| expected_revision != revision) {
+            return Err(Error::Conflict {
+                content: download_file(google, token, id).await?,
+                revision,
+            });
+        }
+        return update_file_media(google, token, id, state.as_mime_type(), content).await;
+    }
+    let document = get_document_state(google, id, token).await?;
+    if expected.is_some_and(|expected_revision| expected_revision != document.revision_id) {
+        return Err(Error::Conflict {
+            content: export_document(google, id, token).await?,
+            revision: document.revision_id,
+        });
+    }
+    let requests = document
+        .text
+        .to_requests(&content.replace("\r\n", "\n"))
+        .ok_or_else(|| Error::Deserialise("Failed to map the document's text.".to_owned()))?;
+    if requests.is_empty() {
+        return Ok(document.revision_id);
+    }
+    log!("Updating file {id} with {} requests.", requests.len());
+
+    // The required revision makes retries safe: an update that was applied
+    // by an attempt whose response was lost can't be applied twice.
+    let response = google
+        .send_idempotent(
+            google
+                .as_client()
+                .post(format!("{}:batchUpdate", google.document_url(id)))
+                .bearer_auth(token)
+                .header("Content-Type", "application/json")
+                .json(&json!({
+                    "requests": requests,
+                    "writeControl": { "requiredRevisionId": document.revision_id },
+                })),
+        )
+        .await?;
+
+    if !response.status().is_success() {
+        let err = error_from(response).await;
+        // The write control rejects the update if the document moved on
+        // since its length was read.
+        let current = get_document_state(google, id, token).await?;
+        return if current.revision_id == document.revision_id {
+            Err(err)
+        } else if current
+            .text
+            .to_requests(content)
+            .is_some_and(|remaining| remaining.is_empty())
+        {
+            // Already updated by a retried attempt.
+            Ok(current.revision_id)
+        } else {
+            Err(Error::Conflict {
+                content: export_document(google, id, token).await?,
+                revision: current.revision_id,
+            })
+        };
     }
+    let body = text(response).await?;
+    serde_json::from_str::<Value>(&body)
+        .map_err(|err| Error::deserialise(&err, "JSON", &body))?
+        .get("writeControl")
+        .and_then(|value| value.get("requiredRevisionId"))
+        .and_then(Value::as_str)
+        .map(str::to_owned)
+        .ok_or_else(|| Error::Deserialise(format!("Failed to get revision from:\n{body}")))
 }
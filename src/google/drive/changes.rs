@@ -0,0 +1,108 @@
+use serde::Deserialize;
+
+use super::interface::{DriveFile, FILE_FIELDS, MAX_PAGE_SIZE};
+use crate::api::GoogleApi;
+use crate::error::{Error, Result};
+use crate::storage::NoteMetadata;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct StartPageToken {
+    start_page_token: String,
+}
+
+/// Change of a file of the Drive, as listed by `changes.list`.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Change {
+    file_id: String,
+    #[serde(default)]
+    removed: bool,
+    file: Option<DriveFile>,
+}
+
+impl Change {
+    pub fn as_file_id(&self) -> &str {
+        &self.file_id
+    }
+
+    /// Returns the file as it is after the change, unless it was deleted,
+    /// trashed or is no longer shared with the user.
+    pub fn into_file(self) -> Option<NoteMetadata> {
+        if self.removed {
+            return None;
+        }
+        self.file
+            .map(NoteMetadata::from)
+            .filter(|file| file.trashed != Some(true))
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ChangePage {
+    #[serde(default)]
+    changes: Vec<Change>,
+    next_page_token: Option<String>,
+    /// Only sent with the last page.
+    new_start_page_token: Option<String>,
+}
+
+/// Returns the page token the changes made from now on are listed from.
+pub async fn get_start_page_token(google: &GoogleApi, token: &str) -> Result<String> {
+    let body = google
+        .send_idempotent_and_text(
+            google
+                .as_client()
+                .get(format!("{}/startPageToken", google.changes_url()))
+                .bearer_auth(token),
+        )
+        .await?;
+    serde_json::from_str::<StartPageToken>(&body)
+        .map(|start| start.start_page_token)
+        .map_err(|err| Error::deserialise(&err, "StartPageToken", &body))
+}
+
+/// Lists every change made since the page token.
+///
+/// Returns the changes, oldest first, with the page token to list the next
+/// ones from.
+pub async fn list_changes(
+    google: &GoogleApi,
+    token: &str,
+    page_token: &str,
+) -> Result<(Vec<Change>, String)> {
+    let fields =
+        format!("nextPageToken,newStartPageToken,changes(fileId,removed,file({FILE_FIELDS}))");
+    let page_size = MAX_PAGE_SIZE.to_string();
+    let mut changes = Vec::new();
+    let mut next_page = page_token.to_owned();
+    loop {
+        let body = google
+            .send_idempotent_and_text(
+                google
+                    .as_client()
+                    .get(google.changes_url())
+                    .bearer_auth(token)
+                    .query(&[
+                        ("pageToken", next_page.as_str()),
+                        ("pageSize", page_size.as_str()),
+                        ("fields", fields.as_str()),
+                        ("spaces", "drive"),
+                    ]),
+            )
+            .await?;
+        let page: ChangePage = serde_json::from_str(&body)
+            .map_err(|err| Error::deserialise(&err, "ChangePage", &body))?;
+        changes.extend(page.changes);
+        match (page.next_page_token, page.new_start_page_token) {
+            (Some(token_of_next_page), _) => next_page = token_of_next_page,
+            (None, Some(start)) => return Ok((changes, start)),
+            (None, None) => {
+                return Err(Error::Internal(
+                    "Drive sent neither the next page nor the new start of the changes.".to_owned(),
+                ));
+            }
+        }
+    }
+}
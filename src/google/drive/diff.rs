@@ -0,0 +1,255 @@
+use core::ops::Range;
+use core::time::Duration;
+use std::time::Instant;
+
+use serde_json::{Value, json};
+use similar::{Algorithm, DiffOp, capture_diff_slices_deadline};
+
+/// Time after which the diff gives up on finding the smallest edit, and
+/// settles for a coarser one.
+const DIFF_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Text of the body of a Google Doc, with the index of each character.
+///
+/// Docs indexes the body in UTF-16 code units, and elements that aren't text,
+/// like tables, occupy indices too, so the characters aren't contiguous.
+pub struct DocumentText {
+    chars: Vec<char>,
+    /// Index of each character in the document.
+    indices: Vec<i64>,
+    /// Index of the last newline of the body, that can't be deleted.
+    end: i64,
+    /// Start of the body, if it holds tables or a table of contents, whose
+    /// text isn't read: the whole body is then replaced.
+    structured_start: Option<i64>,
+}
+
+impl DocumentText {
+    /// Reads the text of the paragraphs of a document returned by
+    /// `documents.get`.
+    pub fn from_document(document: &Value) -> Option<Self> {
+        let content = document.get("body")?.get("content")?.as_array()?;
+        let is_structured = content.iter().any(|element| {
+            element.get("paragraph").is_none() && element.get("sectionBreak").is_none()
+        });
+        let structured_start = if is_structured {
+            Some(
+                content
+                    .iter()
+                    .find(|element| element.get("sectionBreak").is_none())?
+                    .get("startIndex")
+                    .and_then(Value::as_i64)?,
+            )
+        } else {
+            None
+        };
+        let mut chars = Vec::new();
+        let mut indices = Vec::new();
+        for element in content
+            .iter()
+            .filter_map(|value| value.get("paragraph")?.get("elements")?.as_array())
+            .flatten()
+        {
+            let (Some(mut index), Some(text)) = (
+                element.get("startIndex").and_then(Value::as_i64),
+                element
+                    .get("textRun")
+                    .and_then(|run| run.get("content"))
+                    .and_then(Value::as_str),
+            ) else {
+                continue;
+            };
+            for char in text.chars() {
+                chars.push(char);
+                indices.push(index);
+                index = index.saturating_add(char.len_utf16().try_into().ok()?);
+            }
+        }
+        let end = content
+            .last()?
+            .get("endIndex")
+            .and_then(Value::as_i64)?
+            .saturating_sub(1);
+        if indices.last() == Some(&end) {
+            chars.pop();
+            indices.pop();
+        }
+        Some(Self { chars, indices, end, structured_start })
+    }
+
+    /// Index where a character inserted before the `position`-th one goes.
+    fn to_index(&self, position: usize) -> Option<i64> {
+        if position == self.chars.len() {
+            Some(self.end)
+        } else {
+            self.indices.get(position).copied()
+        }
+    }
+
+    /// Index ranges of the characters of the positions, split where
+    /// elements that aren't text lie between them, in reverse order.
+    fn to_ranges(&self, positions: Range<usize>) -> Option<Vec<(i64, i64)>> {
+        let mut ranges: Vec<(i64, i64)> = Vec::new();
+        for position in positions {
+            let start = *self.indices.get(position)?;
+            let end = start.checked_add(self.chars.get(position)?.len_utf16().try_into().ok()?)?;
+            match ranges.last_mut() {
+                Some(range) if range.1 == start => range.1 = end,
+                Some(_) | None => ranges.push((start, end)),
+            }
+        }
+        ranges.reverse();
+        Some(ranges)
+    }
+
+    /// Builds the `batchUpdate` requests turning the document into the new
+    /// content, deleting and inserting only what changed.
+    ///
+    /// The requests are in reverse index order, so that each one leaves the
+    /// indices of the next ones untouched.
+    pub fn to_requests(&self, new_content: &str) -> Option<Vec<Value>> {
+        if let Some(start) = self.structured_start {
+            return Some(self.to_replacement(start, new_content));
+        }
+        let new_chars: Vec<char> = new_content.chars().collect();
+        let ops = capture_diff_slices_deadline(
+            Algorithm::Myers,
+            &self.chars,
+            &new_chars,
+            Instant::now().checked_add(DIFF_TIMEOUT),
+        );
+        let mut requests = Vec::new();
+        for op in ops.iter().rev() {
+            let (old_index, old_len, new_range) = match *op {
+                DiffOp::Equal { .. } => continue,
+                DiffOp::Delete { old_index, old_len, .. } => (old_index, old_len, None),
+                DiffOp::Insert { old_index, new_index, new_len } =>
+                    (old_index, 0, Some(new_index..new_index.checked_add(new_len)?)),
+                DiffOp::Replace { old_index, old_len, new_index, new_len } =>
+                    (old_index, old_len, Some(new_index..new_index.checked_add(new_len)?)),
+            };
+            let start = self.to_index(old_index)?;
+            for (start_index, end_index) in
+                self.to_ranges(old_index..old_index.checked_add(old_len)?)?
+            {
+                requests.push(json!({
+                    "deleteContentRange": {
+                        "range": { "startIndex": start_index, "endIndex": end_index }
+                    }
+                }));
+            }
+            if let Some(range) = new_range {
+                requests.push(json!({
+                    "insertText": {
+                        "text": new_chars.get(range)?.iter().collect::<String>(),
+                        "location": { "index": start },
+                    }
+                }));
+            }
+        }
+        Some(requests)
+    }
+
+    /// Builds the requests replacing the whole body with the new content.
+    fn to_replacement(&self, start: i64, new_content: &str) -> Vec<Value> {
+        let mut requests = Vec::new();
+        if start < self.end {
+            requests.push(json!({
+                "deleteContentRange": { "range": { "startIndex": start, "endIndex": self.end } }
+            }));
+        }
+        if !new_content.is_empty() {
+            requests.push(json!({
+                "insertText": { "text": new_content, "location": { "index": start } }
+            }));
+        }
+        requests
+    }
+}
+
+#[cfg(test)]
+#[expect(clippy::unwrap_used, clippy::indexing_slicing, reason = "tests")]
+mod tests {
+    use serde_json::{Value, json};
+
+    use super::DocumentText;
+
+    /// Paragraph of text runs, each given with its start index.
+    fn paragraph(runs: &[(i64, &str)]) -> Value {
+        let elements: Vec<Value> = runs
+            .iter()
+            .map(|(start, text)| json!({ "startIndex": start, "textRun": { "content": text } }))
+            .collect();
+        json!({ "startIndex": runs[0].0, "paragraph": { "elements": elements } })
+    }
+
+    /// Document with the elements after the initial section break, the last
+    /// one ending at `end`.
+    fn document(mut elements: Vec<Value>, end: i64) -> DocumentText {
+        elements.insert(0, json!({ "endIndex": 1i64, "sectionBreak": {} }));
+        elements.last_mut().unwrap()["endIndex"] = end.into();
+        DocumentText::from_document(&json!({ "body": { "content": elements } })).unwrap()
+    }
+
+    fn insert(text: &str, index: i64) -> Value {
+        json!({ "insertText": { "text": text, "location": { "index": index } } })
+    }
+
+    fn delete(start: i64, end: i64) -> Value {
+        json!({ "deleteContentRange": { "range": { "startIndex": start, "endIndex": end } } })
+    }
+
+    #[test]
+    fn insertions_go_before_the_next_character() {
+        let text = document(vec![paragraph(&[(1, "hello world\n")])], 13);
+
+        assert_eq!(text.to_requests("hello big world").unwrap(), [insert("big ", 7)]);
+        assert_eq!(text.to_requests("hello world!").unwrap(), [insert("!", 12)]);
+        assert!(text.to_requests("hello world").unwrap().is_empty());
+    }
+
+    #[test]
+    fn indices_count_utf16_code_units() {
+        let text = document(vec![paragraph(&[(1, "\u{1f600}a\n")])], 5);
+
+        assert_eq!(text.to_requests("\u{1f600}").unwrap(), [delete(3, 4)]);
+        assert_eq!(text.to_requests("\u{1f600}ba").unwrap(), [insert("b", 3)]);
+    }
+
+    #[test]
+    fn deletions_skip_the_elements_that_arent_text() {
+        // An inline object lies at index 3, between `b` and `c`.
+        let text = document(vec![paragraph(&[(1, "ab"), (4, "cd\n")])], 7);
+
+        let requests = text.to_requests("ad").unwrap();
+
+        assert_eq!(requests, [delete(4, 5), delete(2, 3)]);
+    }
+
+    #[test]
+    fn edits_are_sent_from_the_end_of_the_document() {
+        let text = document(vec![paragraph(&[(1, "one\n")]), paragraph(&[(5, "two\n")])], 9);
+
+        let requests = text.to_requests("One\ntwo!").unwrap();
+
+        assert_eq!(requests[0], insert("!", 8));
+        assert_eq!(requests[1..], [delete(1, 2), insert("O", 1)]);
+    }
+
+    #[test]
+    fn tables_replace_the_whole_body() {
+        let table = json!({ "startIndex": 7i64, "endIndex": 20i64, "table": {} });
+        let text = document(
+            vec![
+                paragraph(&[(1, "intro\n")]),
+                table,
+                paragraph(&[(20, "\n")]),
+            ],
+            21,
+        );
+
+        let requests = text.to_requests("intro\ncells").unwrap();
+
+        assert_eq!(requests, [delete(1, 20), insert("intro\ncells", 1)]);
+    }
+}
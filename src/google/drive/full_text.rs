@@ -0,0 +1,245 @@
+use core::ops::Range;
+use std::collections::HashSet;
+
+use async_lock::Semaphore;
+use futures_util::future::join_all;
+use serde::Serialize;
+
+use super::action::get_note;
+use super::interface::{FileType, SearchQuery, load_all_files};
+use super::query::Query;
+use crate::api::GoogleApi;
+use crate::error::{Error, Result};
+use crate::log;
+use crate::storage::{NoteList, NoteMetadata};
+use crate::view::render::escape;
+
+/// Fields of the files found, in Drive's syntax.
+const SEARCH_FIELDS: &str = "nextPageToken,files(id,kind,name,mimeType,modifiedTime,parents)";
+
+/// Folders put in a single query, to keep its `q` parameter short.
+pub const MAX_PARENTS_PER_QUERY: usize = 20;
+
+/// Deepest level of folders searched under the app folder.
+const MAX_DEPTH: usize = 10;
+
+const DEFAULT_PAGE_SIZE: usize = 20;
+
+const MAX_PAGE_SIZE: usize = 100;
+
+/// Notes downloaded at the same time for their snippets.
+pub const MAX_CONCURRENT_DOWNLOADS: usize = 4;
+
+/// Characters kept on each side of the hit in a snippet.
+const SNIPPET_CONTEXT: usize = 60;
+
+/// Marks the text cut at the ends of a snippet.
+const ELLIPSIS: &str = "\u{2026}";
+
+/// Note matching a full-text search.
+#[derive(Serialize)]
+pub struct SearchHit {
+    #[serde(flatten)]
+    pub file: NoteMetadata,
+    /// Text around the first hit in the content, escaped as HTML with the
+    /// hits wrapped in `<mark>`, missing if only the name matched.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snippet: Option<String>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SearchResults {
+    pub files: Vec<SearchHit>,
+    /// Index of the first hit of the next page.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_page_token: Option<String>,
+}
+
+/// Searches the text in the notes of a folder and its subfolders.
+///
+/// Drive returns the files of each query by relevance; the notes whose name
+/// contains the text are ranked first. Only the notes of the requested page
+/// are downloaded for their snippets, and those failing to download are
+/// returned without snippet.
+pub async fn search_notes(
+    google: &GoogleApi,
+    token: &str,
+    folder_id: &str,
+    text: &str,
+    page: &SearchQuery,
+) -> Result<SearchResults> {
+    let folders = subtree_folders(google, token, folder_id).await?;
+    let mut found_ids = HashSet::new();
+    let mut notes = Vec::new();
+    for parents in folders.chunks(MAX_PARENTS_PER_QUERY) {
+        let query = Query::new()
+            .full_text_contains(text)
+            .parent_in_any(parents)
+            .note()
+            .not_trashed();
+        notes.extend(
+            list_files(google, token, &query)
+                .await?
+                .into_iter()
+                .filter(|note| found_ids.insert(note.id.clone())),
+        );
+    }
+    // Stable, so that Drive's relevance is kept between equal names.
+    notes.sort_by_key(|note| find_ignoring_case(&note.name, text).is_none());
+
+    let (range, next_page_token) = to_page_range(page, notes.len())?;
+    let permits = Semaphore::new(MAX_CONCURRENT_DOWNLOADS);
+    let files = join_all(
+        notes
+            .into_iter()
+            .take(range.end)
+            .skip(range.start)
+            .map(|note| async {
+                let downloaded = {
+                    let _permit = permits.acquire().await;
+                    get_note(google, &note.id, token).await
+                };
+                let snippet = downloaded
+                    .inspect_err(|err| log!("Failed to download a note for its snippet:\n{err}"))
+                    .ok()
+                    .and_then(|found| to_snippet(&found.content, text));
+                SearchHit { snippet, file: note }
+            }),
+    )
+    .await;
+    Ok(SearchResults { files, next_page_token })
+}
+
+/// Returns the range of the requested page among the results, with the token
+/// of the next page.
+pub fn to_page_range(page: &SearchQuery, total: usize) -> Result<(Range<usize>, Option<String>)> {
+    let start: usize = page
+        .as_page_token()
+        .map_or(Ok(0), str::parse)
+        .map_err(|_err| Error::BadRequest("Invalid page token.".to_owned()))?;
+    let page_size = page
+        .as_page_size()
+        .map_or(DEFAULT_PAGE_SIZE, usize::from)
+        .clamp(1, MAX_PAGE_SIZE);
+    let end = start.saturating_add(page_size).min(total);
+    Ok((start.min(end)..end, (end < total).then(|| end.to_string())))
+}
+
+/// Lists the folder and its subfolders, level by level.
+pub async fn subtree_folders(
+    google: &GoogleApi,
+    token: &str,
+    folder_id: &str,
+) -> Result<Vec<String>> {
+    let mut folders = vec![folder_id.to_owned()];
+    let mut level = folders.clone();
+    for _ in 0..MAX_DEPTH {
+        let mut subfolders = Vec::new();
+        for parents in level.chunks(MAX_PARENTS_PER_QUERY) {
+            let query = Query::new()
+                .parent_in_any(parents)
+                .file_type(&FileType::Folder)
+                .not_trashed();
+            subfolders.extend(
+                list_files(google, token, &query)
+                    .await?
+                    .into_iter()
+                    .map(|folder| folder.id),
+            );
+        }
+        if subfolders.is_empty() {
+            break;
+        }
+        folders.extend(subfolders.iter().cloned());
+        level = subfolders;
+    }
+    Ok(folders)
+}
+
+pub async fn list_files(
+    google: &GoogleApi,
+    token: &str,
+    query: &Query,
+) -> Result<Vec<NoteMetadata>> {
+    let search_string = query.to_string();
+    let files =
+        load_all_files(google, &[("q", &search_string), ("fields", SEARCH_FIELDS)], token).await?;
+    Ok(NoteList::from(files).files)
+}
+
+/// Returns the text around the whole text in the content, or else around its
+/// first word found.
+fn to_snippet(content: &str, text: &str) -> Option<String> {
+    let (start, end) = find_ignoring_case(content, text).or_else(|| {
+        text.split_whitespace()
+            .find_map(|word| find_ignoring_case(content, word))
+    })?;
+    to_marked_snippet(content, &[Range { start, end }])
+}
+
+/// Returns the text around the first hit on a single line, escaped as HTML
+/// with the hits wrapped in `<mark>`, the same for both kinds of searches.
+pub fn to_marked_snippet(content: &str, hits: &[Range<usize>]) -> Option<String> {
+    let first = hits.first()?;
+    let around = to_context(content, first.start, first.end)?;
+    let mut snippet = String::new();
+    if around.start > 0 {
+        snippet.push_str(ELLIPSIS);
+    }
+    let mut cursor = around.start;
+    for hit in hits.iter().filter(|hit| hit.end <= around.end) {
+        snippet.push_str(&escape(content.get(cursor..hit.start)?));
+        snippet.push_str("<mark>");
+        snippet.push_str(&escape(content.get(hit.clone())?));
+        snippet.push_str("</mark>");
+        cursor = hit.end;
+    }
+    snippet.push_str(&escape(content.get(cursor..around.end)?));
+    if around.end < content.len() {
+        snippet.push_str(ELLIPSIS);
+    }
+    Some(snippet.split_whitespace().collect::<Vec<_>>().join(" "))
+}
+
+/// Returns the byte range of the text around a hit, with at most
+/// [`SNIPPET_CONTEXT`] characters on each side.
+fn to_context(content: &str, start: usize, end: usize) -> Option<Range<usize>> {
+    let context_start = content
+        .get(..start)?
+        .char_indices()
+        .rev()
+        .nth(SNIPPET_CONTEXT.saturating_sub(1))
+        .map_or(0, |(index, _)| index);
+    let after = content.get(end..)?;
+    let context_end = after
+        .char_indices()
+        .nth(SNIPPET_CONTEXT)
+        .map_or(after.len(), |(index, _)| index);
+    Some(context_start..end.saturating_add(context_end))
+}
+
+/// Returns the byte range of the first occurrence of the text, whatever the
+/// case of its letters.
+fn find_ignoring_case(haystack: &str, text: &str) -> Option<(usize, usize)> {
+    let lowered: Vec<char> = text.chars().flat_map(char::to_lowercase).collect();
+    if lowered.is_empty() {
+        return None;
+    }
+    haystack.char_indices().find_map(|(start, _)| {
+        let mut expected = lowered.iter();
+        let mut end = start;
+        for ch in haystack.get(start..)?.chars() {
+            if expected.len() == 0 {
+                break;
+            }
+            for lower in ch.to_lowercase() {
+                if expected.next() != Some(&lower) {
+                    return None;
+                }
+            }
+            end = end.saturating_add(ch.len_utf8());
+        }
+        (expected.len() == 0).then_some((start, end))
+    })
+}
@@ -0,0 +1,599 @@
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+use core::fmt::{self, Display};
+use core::ops::Range;
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use actix_web::web;
+use async_lock::Semaphore;
+use futures_util::future::try_join_all;
+use serde::{Deserialize, Serialize};
+
+use super::action::get_note;
+use super::changes::{get_start_page_token, list_changes};
+use super::full_text::{
+    MAX_CONCURRENT_DOWNLOADS, MAX_PARENTS_PER_QUERY, SearchHit, SearchResults, list_files, subtree_folders, to_marked_snippet, to_page_range
+};
+use super::interface::{FileType, SearchQuery, is_note_mime_type};
+use super::query::Query;
+use crate::api::GoogleApi;
+use crate::error::{Error, Result};
+use crate::log;
+use crate::state::unlock;
+use crate::storage::NoteMetadata;
+
+/// Positions skipped between the name and the content of a note, so that
+/// phrases don't match across them.
+const NAME_GAP: u32 = 16;
+
+/// Part of a note a term was found in, setting the weight of its hits.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Field {
+    Name,
+    Heading,
+    Tag,
+    Body,
+}
+
+impl Field {
+    const fn to_boost(self) -> u32 {
+        match self {
+            Self::Name => 4,
+            Self::Heading => 3,
+            Self::Tag => 2,
+            Self::Body => 1,
+        }
+    }
+}
+
+/// Word of a note, lowercased, with its byte range in the name or content.
+struct Token {
+    term: String,
+    field: Field,
+    position: u32,
+    range: Range<usize>,
+}
+
+/// Splits a note into its words: `#tag` words are tags, and the words of the
+/// lines starting with `#` are headings.
+fn tokenise(name: &str, content: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut position = 0;
+    push_tokens(&mut tokens, &mut position, name, 0, Field::Name);
+    position = position.saturating_add(NAME_GAP);
+    let mut offset = 0;
+    for line in content.split_inclusive('\n') {
+        let field = if line.trim_start().starts_with('#')
+            && line.trim_start().trim_start_matches('#').starts_with(' ')
+        {
+            Field::Heading
+        } else {
+            Field::Body
+        };
+        push_tokens(&mut tokens, &mut position, line, offset, field);
+        offset = offset.saturating_add(line.len());
+    }
+    tokens
+}
+
+fn push_tokens(
+    tokens: &mut Vec<Token>,
+    position: &mut u32,
+    text: &str,
+    offset: usize,
+    field: Field,
+) {
+    let mut start = None;
+    let mut previous = None;
+    for (index, ch) in text.char_indices().chain([(text.len(), ' ')]) {
+        match (ch.is_alphanumeric(), start) {
+            (true, None) => start = Some((index, previous == Some('#'))),
+            (false, Some((word_start, is_tag))) => {
+                tokens.push(Token {
+                    term: text
+                        .get(word_start..index)
+                        .unwrap_or_default()
+                        .to_lowercase(),
+                    field: if is_tag && field == Field::Body {
+                        Field::Tag
+                    } else {
+                        field
+                    },
+                    position: *position,
+                    range: offset.saturating_add(word_start)..offset.saturating_add(index),
+                });
+                *position = position.saturating_add(1);
+                start = None;
+            }
+            _ => {}
+        }
+        previous = Some(ch);
+    }
+}
+
+/// Part of a search: words in a row, a word, or the start of words with `*`.
+enum Clause {
+    Phrase(Vec<String>),
+    Term(String),
+    Prefix(String),
+}
+
+impl Clause {
+    fn matches(&self, term: &str) -> bool {
+        match self {
+            Self::Phrase(terms) => terms.iter().any(|phrase_term| phrase_term == term),
+            Self::Term(clause_term) => clause_term == term,
+            Self::Prefix(prefix) => term.starts_with(prefix.as_str()),
+        }
+    }
+}
+
+/// Parses a search like `"borrow checker" lifetime*`.
+fn parse_clauses(text: &str) -> Vec<Clause> {
+    let terms = |words: &str| -> Vec<String> {
+        tokenise("", words)
+            .into_iter()
+            .map(|token| token.term)
+            .collect()
+    };
+    let mut clauses = Vec::new();
+    // The parts between quotes are phrases.
+    for (part, is_phrase) in text.split('"').zip([false, true].into_iter().cycle()) {
+        let words = if is_phrase {
+            vec![part]
+        } else {
+            part.split_whitespace().collect()
+        };
+        for word in words {
+            let mut word_terms = terms(word);
+            match (word_terms.len(), word.strip_suffix('*')) {
+                (0, _) => {}
+                (1, Some(_)) if !is_phrase => {
+                    clauses.extend(word_terms.pop().map(Clause::Prefix));
+                }
+                (1, _) => clauses.extend(word_terms.pop().map(Clause::Term)),
+                _ => clauses.push(Clause::Phrase(word_terms)),
+            }
+        }
+    }
+    clauses
+}
+
+/// Note as indexed, with its content for the snippets.
+#[derive(Serialize, Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct IndexedNote {
+    name: String,
+    mime_type: String,
+    modified_time: Option<String>,
+    parents: Vec<String>,
+    content: String,
+}
+
+impl IndexedNote {
+    fn new(file: NoteMetadata, content: String) -> Self {
+        Self {
+            name: file.name,
+            mime_type: file.mime_type,
+            modified_time: file.modified_time,
+            parents: file.parents,
+            content,
+        }
+    }
+
+    fn to_metadata(&self, id: &str) -> NoteMetadata {
+        NoteMetadata {
+            id: id.to_owned(),
+            kind: "drive#file".to_owned(),
+            mime_type: self.mime_type.clone(),
+            name: self.name.clone(),
+            modified_time: self.modified_time.clone(),
+            parents: self.parents.clone(),
+            ..NoteMetadata::default()
+        }
+    }
+
+    /// Returns the text around the first hit of the clauses in the content.
+    fn to_snippet(&self, clauses: &[Clause]) -> Option<String> {
+        let hits: Vec<Range<usize>> = tokenise("", &self.content)
+            .into_iter()
+            .filter(|token| clauses.iter().any(|clause| clause.matches(&token.term)))
+            .map(|token| token.range)
+            .collect();
+        to_marked_snippet(&self.content, &hits)
+    }
+}
+
+/// Fields and positions of the hits of a term in a note.
+type Hits = Vec<(Field, u32)>;
+
+/// Index of the notes of one app folder, saved as JSON.
+#[derive(Serialize, Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct FolderIndex {
+    /// Page token of the changes feed the index is up to date with, missing
+    /// until the index is built.
+    changes_token: Option<String>,
+    /// App folder and its subfolders, the notes elsewhere being left out.
+    folders: HashSet<String>,
+    notes: HashMap<String, IndexedNote>,
+    /// Hits of each term, by note id, sorted for the prefix searches.
+    postings: BTreeMap<String, BTreeMap<String, Hits>>,
+}
+
+impl FolderIndex {
+    fn insert(&mut self, id: String, note: IndexedNote) {
+        drop(self.remove(&id));
+        for token in tokenise(&note.name, &note.content) {
+            self.postings
+                .entry(token.term)
+                .or_default()
+                .entry(id.clone())
+                .or_default()
+                .push((token.field, token.position));
+        }
+        self.notes.insert(id, note);
+    }
+
+    fn remove(&mut self, id: &str) -> Option<IndexedNote> {
+        let note = self.notes.remove(id)?;
+        for token in tokenise(&note.name, &note.content) {
+            if let Some(notes) = self.postings.get_mut(&token.term) {
+                notes.remove(id);
+                if notes.is_empty() {
+                    self.postings.remove(&token.term);
+                }
+            }
+        }
+        Some(note)
+    }
+
+    fn is_in_folders(&self, file: &NoteMetadata) -> bool {
+        file.parents
+            .iter()
+            .any(|parent| self.folders.contains(parent))
+    }
+
+    /// Returns the score of every note matching the clause.
+    fn to_scores(&self, clause: &Clause) -> HashMap<&str, u32> {
+        let mut scores: HashMap<&str, u32> = HashMap::new();
+        let mut add = |id: &'_ str, score: u32| {
+            if let Some((id_key, _)) = self.notes.get_key_value(id) {
+                let total = scores.entry(id_key.as_str()).or_default();
+                *total = total.saturating_add(score);
+            }
+        };
+        match clause {
+            Clause::Term(term) =>
+                for (id, hits) in self.postings.get(term).into_iter().flatten() {
+                    add(id, hits.iter().map(|(field, _)| field.to_boost()).sum());
+                },
+            Clause::Prefix(prefix) => {
+                for (_, notes) in self
+                    .postings
+                    .range(prefix.clone()..)
+                    .take_while(|(term, _)| term.starts_with(prefix.as_str()))
+                {
+                    for (id, hits) in notes {
+                        add(id, hits.iter().map(|(field, _)| field.to_boost()).sum());
+                    }
+                }
+            }
+            Clause::Phrase(terms) => {
+                let Some((first, rest)) = terms.split_first() else {
+                    return scores;
+                };
+                for (id, hits) in self.postings.get(first).into_iter().flatten() {
+                    let score: u32 = hits
+                        .iter()
+                        .filter(|(field, position)| {
+                            rest.iter().zip(1u32..).all(|(term, offset)| {
+                                self.postings
+                                    .get(term)
+                                    .and_then(|notes| notes.get(id))
+                                    .is_some_and(|term_hits| {
+                                        term_hits
+                                            .contains(&(*field, position.saturating_add(offset)))
+                                    })
+                            })
+                        })
+                        .map(|(field, _)| field.to_boost())
+                        .sum();
+                    if score > 0 {
+                        add(id, score);
+                    }
+                }
+            }
+        }
+        scores
+    }
+
+    /// Returns the ids of the notes matching every clause, best first.
+    fn search(&self, clauses: &[Clause]) -> Vec<&str> {
+        let mut matches: Option<HashMap<&str, u32>> = None;
+        for clause in clauses {
+            let scores = self.to_scores(clause);
+            matches = Some(match matches {
+                None => scores,
+                Some(previous) => previous
+                    .into_iter()
+                    .filter_map(|(id, score)| {
+                        scores
+                            .get(id)
+                            .map(|clause_score| (id, score.saturating_add(*clause_score)))
+                    })
+                    .collect(),
+            });
+        }
+        let mut ranked: Vec<(&str, u32)> = matches.unwrap_or_default().into_iter().collect();
+        ranked.sort_by(|(left_id, left), (right_id, right)| {
+            right.cmp(left).then_with(|| {
+                let name = |id: &str| self.notes.get(id).map(|note| note.name.as_str());
+                name(left_id).cmp(&name(right_id))
+            })
+        });
+        ranked.into_iter().map(|(id, _)| id).collect()
+    }
+}
+
+/// Index of one app folder, with the lock of its updates.
+#[derive(Debug, Default)]
+struct FolderSlot {
+    index: Mutex<FolderIndex>,
+    /// Held while the index is loaded from the disk, built or updated from
+    /// the changes feed, telling whether it was loaded.
+    sync_lock: async_lock::Mutex<bool>,
+}
+
+impl FolderSlot {
+    fn with_index<T>(&self, call: impl FnOnce(&mut FolderIndex) -> T) -> Result<T> {
+        let mut index = unlock(&self.index, "search index")?;
+        let result = call(&mut index);
+        drop(index);
+        Ok(result)
+    }
+
+    /// Indexes every note of the app folder and its subfolders.
+    async fn build(&self, google: &GoogleApi, token: &str, folder_id: &str) -> Result<()> {
+        log!("Building the search index...");
+        // Taken first, so that the changes made while building are replayed.
+        let changes_token = get_start_page_token(google, token).await?;
+        let folders = subtree_folders(google, token, folder_id).await?;
+        let mut files = Vec::new();
+        for parents in folders.chunks(MAX_PARENTS_PER_QUERY) {
+            let query = Query::new().parent_in_any(parents).note().not_trashed();
+            files.extend(list_files(google, token, &query).await?);
+        }
+        let notes = download_notes(google, token, files).await?;
+        self.with_index(|index| {
+            *index = FolderIndex {
+                changes_token: Some(changes_token),
+                folders: folders.into_iter().collect(),
+                ..FolderIndex::default()
+            };
+            for (id, note) in notes {
+                index.insert(id, note);
+            }
+        })
+    }
+
+    /// Reindexes the notes changed since the last update, rebuilding the
+    /// index if folders of the app folder changed or were moved in.
+    ///
+    /// Returns whether the index changed.
+    async fn apply_changes(
+        &self,
+        google: &GoogleApi,
+        token: &str,
+        folder_id: &str,
+        changes_token: &str,
+    ) -> Result<bool> {
+        let (changes, new_token) = list_changes(google, token, changes_token).await?;
+        if changes.is_empty() {
+            return Ok(false);
+        }
+        let mut removed = Vec::new();
+        let mut updated = Vec::new();
+        for change in changes {
+            let id = change.as_file_id().to_owned();
+            match change.into_file() {
+                Some(file) => updated.push(file),
+                None => removed.push(id),
+            }
+        }
+        let folder_type = FileType::Folder.as_mime_type();
+        let is_tree_changed = self.with_index(|index| {
+            removed.iter().any(|id| index.folders.contains(id))
+                || updated.iter().any(|file| {
+                    file.mime_type == folder_type
+                        && (index.folders.contains(&file.id) || index.is_in_folders(file))
+                })
+        })?;
+        if is_tree_changed {
+            self.build(google, token, folder_id).await?;
+            return Ok(true);
+        }
+        // The other files, and the notes moved out, are only removed.
+        let (inside, outside): (Vec<_>, Vec<_>) = self.with_index(|index| {
+            updated
+                .into_iter()
+                .partition(|file| index.is_in_folders(file) && is_note_mime_type(&file.mime_type))
+        })?;
+        let notes = download_notes(google, token, inside).await?;
+        self.with_index(|index| {
+            for id in removed.iter().chain(outside.iter().map(|file| &file.id)) {
+                drop(index.remove(id));
+            }
+            for (id, note) in notes {
+                index.insert(id, note);
+            }
+            index.changes_token = Some(new_token);
+        })?;
+        Ok(true)
+    }
+}
+
+/// Local full-text index of the notes, saved in a directory with one file by
+/// app folder, so that the users only search their own notes.
+///
+/// It is built on the first search, then brought up to date from Drive's
+/// changes feed before each search, and with the content of every save. It
+/// is only written to the disk by the searches that changed it: the saves
+/// made since are replayed from the changes feed after a restart.
+pub struct SearchIndex {
+    dir: PathBuf,
+    folders: Mutex<HashMap<String, Arc<FolderSlot>>>,
+}
+
+impl fmt::Debug for SearchIndex {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let count = unlock(&self.folders, "search index").map_or(0, |folders| folders.len());
+        f.debug_struct("SearchIndex")
+            .field("dir", &self.dir)
+            .field("folders", &count)
+            .finish()
+    }
+}
+
+impl SearchIndex {
+    pub fn new(dir: PathBuf) -> Self {
+        Self { dir, folders: Mutex::new(HashMap::new()) }
+    }
+
+    fn to_path(&self, folder_id: &str) -> PathBuf {
+        self.dir.join(format!("{folder_id}.json"))
+    }
+
+    fn to_slot(&self, folder_id: &str) -> Result<Arc<FolderSlot>> {
+        Ok(Arc::clone(
+            unlock(&self.folders, "search index")?
+                .entry(folder_id.to_owned())
+                .or_default(),
+        ))
+    }
+
+    /// Reads the saved index of a folder, if any.
+    async fn load(&self, folder_id: &str) -> Result<Option<FolderIndex>> {
+        let path = self.to_path(folder_id);
+        web::block(move || {
+            let saved = fs::read_to_string(path).ok()?;
+            serde_json::from_str(&saved)
+                .inspect_err(|err| log!("Ignoring the corrupted search index: {err}"))
+                .ok()
+        })
+        .await
+        .map_err(|err| Error::Internal(format!("Failed to load the search index: {err}")))
+    }
+
+    async fn save(&self, folder_id: &str, slot: Arc<FolderSlot>) -> Result<()> {
+        let dir = self.dir.clone();
+        let path = self.to_path(folder_id);
+        let to_error =
+            |err: &dyn Display| Error::Internal(format!("Failed to save the search index: {err}"));
+        web::block(move || {
+            let saved = slot
+                .with_index(|index| serde_json::to_string(index))?
+                .map_err(|err| to_error(&err))?;
+            let temporary = path.with_extension("json.tmp");
+            fs::create_dir_all(dir)
+                .and_then(|()| fs::write(&temporary, saved))
+                .and_then(|()| fs::rename(&temporary, &path))
+                .map_err(|err| to_error(&err))
+        })
+        .await
+        .map_err(|err| to_error(&err))?
+    }
+
+    /// Brings the index of the app folder up to date with Drive.
+    pub async fn sync(&self, google: &GoogleApi, token: &str, folder_id: &str) -> Result<()> {
+        let slot = self.to_slot(folder_id)?;
+        let mut is_loaded = slot.sync_lock.lock().await;
+        if !*is_loaded {
+            if let Some(saved) = self.load(folder_id).await? {
+                slot.with_index(|index| *index = saved)?;
+            }
+            *is_loaded = true;
+        }
+        let is_changed =
+            if let Some(changes_token) = slot.with_index(|index| index.changes_token.clone())? {
+                slot.apply_changes(google, token, folder_id, &changes_token)
+                    .await?
+            } else {
+                slot.build(google, token, folder_id).await?;
+                true
+            };
+        if is_changed {
+            self.save(folder_id, Arc::clone(&slot)).await?;
+        }
+        drop(is_loaded);
+        Ok(())
+    }
+
+    /// Reindexes a saved note, if it is in the index.
+    ///
+    /// Failures are only logged, as the note itself was saved.
+    pub fn update_content(&self, folder_id: &str, id: &str, content: &str) {
+        let updated = self.to_slot(folder_id).and_then(|slot| {
+            slot.with_index(|index| {
+                if let Some(note) = index.remove(id) {
+                    index
+                        .insert(id.to_owned(), IndexedNote { content: content.to_owned(), ..note });
+                }
+            })
+        });
+        if let Err(err) = updated {
+            log!("Failed to update the search index:\n{err}");
+        }
+    }
+
+    /// Searches the index of the app folder, which should be synced first.
+    pub fn search(&self, folder_id: &str, text: &str, page: &SearchQuery) -> Result<SearchResults> {
+        let clauses = parse_clauses(text);
+        self.to_slot(folder_id)?.with_index(|index| {
+            let ranked = index.search(&clauses);
+            let (range, next_page_token) = to_page_range(page, ranked.len())?;
+            let files = ranked
+                .get(range)
+                .unwrap_or_default()
+                .iter()
+                .filter_map(|id| {
+                    let note = index.notes.get(*id)?;
+                    Some(SearchHit {
+                        file: note.to_metadata(id),
+                        snippet: note.to_snippet(&clauses),
+                    })
+                })
+                .collect();
+            Ok(SearchResults { files, next_page_token })
+        })?
+    }
+}
+
+/// Downloads the content of the notes, a few at a time, skipping those that
+/// can't be.
+async fn download_notes(
+    google: &GoogleApi,
+    token: &str,
+    files: Vec<NoteMetadata>,
+) -> Result<Vec<(String, IndexedNote)>> {
+    let permits = Semaphore::new(MAX_CONCURRENT_DOWNLOADS);
+    let notes = try_join_all(files.into_iter().map(|file| async {
+        let _permit = permits.acquire().await;
+        match get_note(google, &file.id, token).await {
+            Ok(note) => Ok(Some((file.id.clone(), IndexedNote::new(file, note.content)))),
+            Err(err) if err.is_unauthorised() => Err(err),
+            // Deleted since it was listed, the next changes will tell.
+            Err(Error::NotFound(_)) => Ok(None),
+            Err(err) => {
+                log!("Leaving a note out of the search index:\n{err}");
+                Ok(None)
+            }
+        }
+    }))
+    .await?;
+    Ok(notes.into_iter().flatten().collect())
+}
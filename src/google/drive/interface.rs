@@ -1,27 +1,95 @@
-use core::result;
+use core::cmp::Ordering;
+use core::str::FromStr;
 
-use reqwest::Client;
+use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
-use serde_json::json;
+use serde_json::{Value, json};
 
-use crate::api::send_and_text;
+use super::full_text::{MAX_PARENTS_PER_QUERY, subtree_folders};
+use super::query::Query;
+use crate::api::{GoogleApi, error_from, send_and_text, success_text};
+use crate::error::{Error, Result};
 use crate::log;
+use crate::session::random_token;
+use crate::storage::{NoteList, NoteMetadata, NoteUser, to_markdown_name};
 
-type Result<T, E = String> = result::Result<T, E>;
+/// Fields of the files sent to the client, unless it selects others.
+pub const FILE_FIELDS: &str = "id,kind,mimeType,name,createdTime,modifiedTime,size,parents,\
+                           owners(displayName,emailAddress),\
+                           lastModifyingUser(displayName,emailAddress),starred,trashed,\
+                           webViewLink,version";
 
-#[derive(Deserialize, Serialize, Debug)]
+/// Largest page size accepted by Drive.
+pub const MAX_PAGE_SIZE: u16 = 1000;
+
+/// MIME type of the notes stored as plain Markdown files.
+pub const MARKDOWN_MIME_TYPE: &str = "text/markdown";
+
+/// Tells the notes, Google Docs and Markdown files, from the other files.
+pub fn is_note_mime_type(mime_type: &str) -> bool {
+    mime_type == MARKDOWN_MIME_TYPE || mime_type == FileType::Document.as_mime_type()
+}
+
+/// Only the requested fields are sent by Drive, so the others are left empty.
+#[derive(Deserialize, Serialize, Debug, Default)]
+#[serde(default)]
 #[expect(non_snake_case, reason = "needed by serde")]
 pub struct DriveFile {
     id: String,
     kind: String,
     mimeType: String,
     name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    createdTime: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    modifiedTime: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    parents: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    owners: Vec<NoteUser>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    lastModifyingUser: Option<NoteUser>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    starred: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    trashed: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    webViewLink: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    version: Option<String>,
 }
 
 impl DriveFile {
     pub fn to_id(&self) -> Box<str> {
         self.id.clone().into_boxed_str()
     }
+
+    pub fn is_folder(&self) -> bool {
+        self.mimeType == FileType::Folder.as_mime_type()
+    }
+}
+
+impl From<DriveFile> for NoteMetadata {
+    fn from(file: DriveFile) -> Self {
+        Self {
+            id: file.id,
+            kind: file.kind,
+            mime_type: file.mimeType,
+            name: file.name,
+            created_time: file.createdTime,
+            modified_time: file.modifiedTime,
+            size: file.size,
+            parents: file.parents,
+            owners: file.owners,
+            last_modifying_user: file.lastModifyingUser,
+            starred: file.starred,
+            trashed: file.trashed,
+            web_view_link: file.webViewLink,
+            version: file.version,
+        }
+    }
 }
 
 macro_rules! make_file_type {
@@ -48,7 +116,7 @@ macro_rules! make_file_type {
 }
 
 impl FileType {
-    fn as_mime_type(&self) -> String {
+    pub fn as_mime_type(&self) -> String {
         format!("application/vnd.google-apps.{}", self.as_str())
     }
 }
@@ -59,101 +127,741 @@ make_file_type!(
     Folder "folder",
 );
 
-#[derive(Deserialize, Serialize)]
+/// How new notes are stored in Drive.
+#[derive(Deserialize, Debug, Default, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+#[non_exhaustive]
+pub enum NoteFormat {
+    /// Google Doc, edited through the Docs API.
+    #[default]
+    Document,
+    /// Plain Markdown file, stored and returned byte for byte.
+    Markdown,
+}
+
+impl FromStr for NoteFormat {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "document" => Ok(Self::Document),
+            "markdown" => Ok(Self::Markdown),
+            _ => Err(format!("Unknown note format {value}, expected `document` or `markdown`.")),
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize, Default)]
+#[serde(default)]
 #[expect(non_snake_case, reason = "needed by serde")]
 pub struct DriveFileList {
-    files: Box<[DriveFile]>,
+    files: Vec<DriveFile>,
     incompleteSearch: bool,
     kind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nextPageToken: Option<String>,
 }
 
 impl DriveFileList {
-    pub fn filter_with_type(self, filetype: &FileType) -> Box<[DriveFile]> {
-        self.files
-            .into_iter()
-            .filter(|file| file.mimeType == filetype.as_mime_type())
-            .collect()
+    /// Appends the files of the next page to the list.
+    fn extend(&mut self, next_page: Self) {
+        self.files.extend(next_page.files);
+        self.incompleteSearch |= next_page.incompleteSearch;
+        self.kind = next_page.kind;
+        self.nextPageToken = next_page.nextPageToken;
     }
 
-    fn find(self, filename: &str, filetype: &FileType) -> Option<DriveFile> {
-        self.files
-            .into_iter()
-            .find(|file| file.name == filename && file.mimeType == filetype.as_mime_type())
+    fn into_first(self) -> Option<DriveFile> {
+        self.files.into_iter().next()
     }
 }
 
-pub async fn create_folder(token: &str, filename: &str) -> Result<DriveFile> {
-    log!("File {filename} not found. Creating...");
+impl From<DriveFileList> for NoteList {
+    fn from(list: DriveFileList) -> Self {
+        Self {
+            files: list.files.into_iter().map(NoteMetadata::from).collect(),
+            incomplete_search: list.incompleteSearch,
+            kind: list.kind,
+            next_page_token: list.nextPageToken,
+        }
+    }
+}
+
+/// Creates a folder, at the root of the Drive if no parent is given.
+pub async fn create_folder(
+    google: &GoogleApi,
+    token: &str,
+    filename: &str,
+    parent: Option<&str>,
+) -> Result<DriveFile> {
+    log!("Creating folder {filename}...");
 
     let metadata = json!({
         "name": filename,
-        "mimeType": format!("application/vnd.google-apps.folder")
-    })
-    .to_string();
+        "mimeType": FileType::Folder.as_mime_type(),
+        "parents": parent.into_iter().collect::<Vec<_>>(),
+    });
+    upload_new_file(google, token, &metadata, None).await
+}
+
+/// Creates an empty Markdown note in a folder, adding the `.md` extension if
+/// it is missing.
+pub async fn create_markdown_file(
+    google: &GoogleApi,
+    token: &str,
+    name: &str,
+    folder_id: &str,
+) -> Result<DriveFile> {
+    let metadata = json!({
+        "name": to_markdown_name(name),
+        "parents": [folder_id],
+        "mimeType": MARKDOWN_MIME_TYPE,
+    });
+    upload_new_file(google, token, &metadata, Some((MARKDOWN_MIME_TYPE, ""))).await
+}
 
-    let boundary = "boundary";
+/// Creates a file with a multipart upload, sending its metadata and, if
+/// given, its content with its MIME type.
+async fn upload_new_file(
+    google: &GoogleApi,
+    token: &str,
+    metadata: &Value,
+    content: Option<(&str, &str)>,
+) -> Result<DriveFile> {
+    let boundary = format!("md-viewer-{}", random_token());
+    let media = content.map_or_else(String::new, |(mime_type, body)| {
+        format!("--{boundary}\r\nContent-Type: {mime_type}\r\n\r\n{body}\r\n")
+    });
     let multipart = format!(
         "--{boundary}\r\n\
          Content-Type: application/json; charset=UTF-8\r\n\r\n\
          {metadata}\r\n\
+         {media}\
          --{boundary}--\r\n",
     );
 
     let content_type = format!("multipart/related; boundary={boundary}");
 
-    match Client::new()
-        .post("https://www.googleapis.com/upload/drive/v3/files?uploadType=multipart")
-        .bearer_auth(token)
-        .header("Content-Type", content_type)
-        .body(multipart)
-        .send()
+    let response = send_and_text(
+        google
+            .as_client()
+            .post(format!("{}?uploadType=multipart", google.upload_url()))
+            .bearer_auth(token)
+            .header("Content-Type", content_type)
+            .body(multipart),
+    )
+    .await?;
+    serde_json::from_str(&response).map_err(|err| Error::deserialise(&err, "DriveFile", &response))
+}
+
+pub async fn load_files(
+    google: &GoogleApi,
+    query: &[(&str, &str)],
+    token: &str,
+) -> Result<DriveFileList> {
+    google
+        .send_idempotent_and_text(
+            google
+                .as_client()
+                .get(google.files_url())
+                .bearer_auth(token)
+                .query(query),
+        )
         .await
-    {
-        Ok(res) => match res.text().await {
-            Ok(text) => serde_json::from_str(&text)
-                .map_err(|err| format!("Failed to serialise response: {err}")),
-            Err(err) => Err(format!("Failed to get text: {err}")),
-        },
-        Err(err) => Err(format!("Failed to post: {err}")),
+        .and_then(|stringified| {
+            serde_json::from_str(&stringified).map_err(|err| {
+                Error::deserialise(&err, &format!("DriveFileList on query {query:?}"), &stringified)
+            })
+        })
+}
+
+/// Loads the files of every page of the query, following `nextPageToken`.
+pub async fn load_all_files(
+    google: &GoogleApi,
+    query: &[(&str, &str)],
+    token: &str,
+) -> Result<DriveFileList> {
+    let page_size = MAX_PAGE_SIZE.to_string();
+    let mut page_query = query.to_vec();
+    page_query.push(("pageSize", &page_size));
+    let mut files = load_files(google, &page_query, token).await?;
+    while let Some(page_token) = files.nextPageToken.take() {
+        let mut next_page_query = page_query.clone();
+        next_page_query.push(("pageToken", &page_token));
+        files.extend(load_files(google, &next_page_query, token).await?);
     }
+    Ok(files)
 }
 
-pub async fn load_files(query: &[(&str, &str)], token: &str) -> Result<DriveFileList> {
-    send_and_text(
-        Client::new()
-            .get("https://www.googleapis.com/drive/v3/files")
-            .bearer_auth(token)
-            .query(query),
-    )
-    .await
-    .and_then(|stringified| {
-        serde_json::from_str(&stringified)
-            .map_err(|err| format!("Failed to deserialise on query {query:?}:\n{err}\n\nData (conversion objective was DriveFileList):\n{stringified}"))
-    })
+/// Key of the order of a file listing.
+enum SortKey {
+    Name,
+    ModifiedTime,
+    CreatedTime,
+}
+
+/// Order of a file listing, written like Drive's `orderBy` (e.g. `name` or
+/// `modifiedTime desc`).
+pub struct Order {
+    key: SortKey,
+    descending: bool,
+}
+
+impl FromStr for Order {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        let (key, descending) = match value.trim().split_once(' ') {
+            Some((key, "desc")) => (key, true),
+            Some((key, "asc")) => (key, false),
+            Some(_) => return Err(Error::BadRequest(format!("Invalid order {value}."))),
+            None => (value.trim(), false),
+        };
+        let sort_key = match key {
+            "name" => SortKey::Name,
+            "modifiedTime" => SortKey::ModifiedTime,
+            "createdTime" => SortKey::CreatedTime,
+            _ => {
+                return Err(Error::BadRequest(format!(
+                    "Unknown order {key}, expected `name`, `modifiedTime` or `createdTime`."
+                )));
+            }
+        };
+        Ok(Self { key: sort_key, descending })
+    }
+}
+
+impl Order {
+    pub const fn is_by_name(&self) -> bool {
+        matches!(self.key, SortKey::Name)
+    }
+
+    pub const fn is_descending(&self) -> bool {
+        self.descending
+    }
+
+    /// Compares files in this order.
+    pub fn compare(&self, left: &NoteMetadata, right: &NoteMetadata) -> Ordering {
+        let ordering = match self.key {
+            SortKey::Name => left.name.cmp(&right.name),
+            SortKey::ModifiedTime => left.modified_time.cmp(&right.modified_time),
+            SortKey::CreatedTime => left.created_time.cmp(&right.created_time),
+        };
+        if self.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        }
+    }
+
+    fn to_drive(&self) -> String {
+        let key = match self.key {
+            SortKey::Name => "name",
+            SortKey::ModifiedTime => "modifiedTime",
+            SortKey::CreatedTime => "createdTime",
+        };
+        if self.descending {
+            format!("{key} desc")
+        } else {
+            key.to_owned()
+        }
+    }
+}
+
+/// Page of a file listing, as requested by the client, with its order and
+/// filters.
+///
+/// If neither a page token nor a page size is given, all the pages are
+/// loaded.
+#[derive(Deserialize, Default, Clone)]
+pub struct PageQuery {
+    /// Fields of the files to return, in Drive's syntax (e.g. `id,name`).
+    fields: Option<String>,
+    /// Folder to list, the app folder if none is given.
+    folder: Option<String>,
+    /// Start of the names of the files.
+    name_prefix: Option<String>,
+    /// Order of the files, like `name` or `modifiedTime desc`.
+    #[serde(alias = "orderBy")]
+    order_by: Option<String>,
+    page_size: Option<u16>,
+    page_token: Option<String>,
+    /// Only keeps the starred files.
+    #[serde(default)]
+    starred: bool,
+    /// Type of the files, like `document` or `folder`.
+    #[serde(rename = "type")]
+    file_type: Option<String>,
+}
+
+impl PageQuery {
+    pub const fn is_selecting_fields(&self) -> bool {
+        self.fields.is_some()
+    }
+
+    /// Tells whether a name starts with the requested prefix, whatever the
+    /// case, the same in every storage.
+    pub fn is_name_matching(&self, name: &str) -> bool {
+        self.name_prefix
+            .as_ref()
+            .is_none_or(|prefix| name.to_lowercase().starts_with(&prefix.to_lowercase()))
+    }
+
+    pub fn to_order(&self) -> Result<Option<Order>> {
+        self.order_by.as_deref().map(str::parse).transpose()
+    }
+
+    pub const fn is_starred_only(&self) -> bool {
+        self.starred
+    }
+
+    pub fn to_file_type(&self) -> Result<Option<FileType>> {
+        self.file_type
+            .as_deref()
+            .map(|file_type| {
+                FileType::from_str(file_type)
+                    .ok_or_else(|| Error::BadRequest(format!("Unknown file type {file_type}.")))
+            })
+            .transpose()
+    }
+
+    /// Adds the filters of the listing to a query.
+    fn to_query(&self, mut query: Query) -> Result<Query> {
+        if let Some(file_type) = self.to_file_type()? {
+            query = query.file_type(&file_type);
+        }
+        if self.starred {
+            query = query.starred();
+        }
+        // Narrowed down to the names starting with the prefix once listed.
+        if let Some(prefix) = &self.name_prefix {
+            query = query.name_contains(prefix);
+        }
+        Ok(query)
+    }
+
+    pub fn as_folder(&self) -> Option<&str> {
+        self.folder.as_deref()
+    }
+
+    /// Returns the requested page size, between 1 and what Drive accepts.
+    pub fn as_page_size(&self) -> Option<u16> {
+        self.page_size.map(|size| size.clamp(1, MAX_PAGE_SIZE))
+    }
+
+    pub fn as_page_token(&self) -> Option<&str> {
+        self.page_token.as_deref()
+    }
+
+    /// Value of Drive's `fields` parameter for a file listing, with the name
+    /// if the files are filtered by it.
+    fn to_list_fields(&self) -> String {
+        let fields = self.fields.as_deref().unwrap_or(FILE_FIELDS);
+        let is_name_missing =
+            self.name_prefix.is_some() && !fields.split(',').any(|field| field.trim() == "name");
+        format!(
+            "nextPageToken,incompleteSearch,kind,files({fields}{})",
+            if is_name_missing { ",name" } else { "" }
+        )
+    }
+}
+
+/// Filters of a search in the app folder, as requested by the client.
+#[derive(Deserialize)]
+pub struct SearchQuery {
+    /// Text searched in the notes of the whole app folder, returned ranked
+    /// with snippets. The other criteria only apply without it.
+    #[serde(rename = "q")]
+    full_text: Option<String>,
+    page_size: Option<u16>,
+    page_token: Option<String>,
+    name: Option<String>,
+    /// Type of the files, like `document` or `folder`.
+    #[serde(rename = "type")]
+    file_type: Option<String>,
+    /// Text contained in the name, description or content of the files.
+    text: Option<String>,
+    /// RFC 3339 date-time the files were modified after.
+    modified_after: Option<String>,
+}
+
+impl SearchQuery {
+    pub fn as_text(&self) -> Option<&str> {
+        self.full_text
+            .as_deref()
+            .filter(|text| !text.trim().is_empty())
+    }
+
+    pub const fn as_page_size(&self) -> Option<u16> {
+        self.page_size
+    }
+
+    pub fn as_page_token(&self) -> Option<&str> {
+        self.page_token.as_deref()
+    }
+
+    fn to_query(&self, folder_id: &str) -> Result<Query> {
+        let mut query = Query::new().parent_in(folder_id).not_trashed();
+        if let Some(name) = &self.name {
+            query = query.name_eq(name);
+        }
+        if let Some(file_type) = &self.file_type {
+            let filetype = FileType::from_str(file_type)
+                .ok_or_else(|| Error::BadRequest(format!("Unknown file type {file_type}.")))?;
+            query = query.file_type(&filetype);
+        }
+        if let Some(text) = &self.text {
+            query = query.full_text_contains(text);
+        }
+        if let Some(time) = &self.modified_after {
+            query = query.modified_after(time);
+        }
+        Ok(query)
+    }
 }
 
 pub async fn root_contains_file(
+    google: &GoogleApi,
     token: &str,
     filename: &str,
     filetype: &FileType,
 ) -> Result<Option<DriveFile>> {
-    load_files(&[("q", "'root' in parents")], token)
+    let query = Query::new()
+        .parent_in("root")
+        .name_eq(filename)
+        .file_type(filetype)
+        .not_trashed()
+        .to_string();
+    load_all_files(google, &[("q", &query)], token)
         .await
-        .map(|files| files.find(filename, filetype))
+        .map(DriveFileList::into_first)
+}
+
+/// MIME type and revision of a file.
+#[derive(Deserialize)]
+#[expect(non_snake_case, reason = "needed by serde")]
+pub struct FileState {
+    /// Revision of the content, only given for plain (non-Google) files.
+    headRevisionId: Option<String>,
+    mimeType: String,
+}
+
+impl FileState {
+    pub fn is_document(&self) -> bool {
+        self.mimeType == FileType::Document.as_mime_type()
+    }
+
+    pub fn as_mime_type(&self) -> &str {
+        &self.mimeType
+    }
+
+    pub fn to_revision(&self) -> Result<String> {
+        self.headRevisionId.clone().ok_or_else(|| {
+            Error::Deserialise(format!(
+                "Drive sent no revision for a file of type {}.",
+                self.mimeType
+            ))
+        })
+    }
+}
+
+/// Returns the MIME type and the revision of a file, telling Google Docs from
+/// plain files.
+pub async fn get_file_state(google: &GoogleApi, token: &str, file_id: &str) -> Result<FileState> {
+    let response = google
+        .send_idempotent(
+            google
+                .as_client()
+                .get(google.file_url(file_id))
+                .bearer_auth(token)
+                .query(&[("fields", "mimeType,headRevisionId")]),
+        )
+        .await?;
+    let body = success_text(response).await?;
+    serde_json::from_str(&body).map_err(|err| Error::deserialise(&err, "FileState", &body))
+}
+
+/// Downloads the content of a plain (non-Google) file.
+pub async fn download_file(google: &GoogleApi, token: &str, file_id: &str) -> Result<String> {
+    let response = google
+        .send_idempotent(
+            google
+                .as_client()
+                .get(google.file_url(file_id))
+                .bearer_auth(token)
+                .query(&[("alt", "media")]),
+        )
+        .await?;
+    success_text(response).await
+}
+
+/// Replaces the content of a plain (non-Google) file with a media upload,
+/// returning its new revision.
+pub async fn update_file_media(
+    google: &GoogleApi,
+    token: &str,
+    file_id: &str,
+    mime_type: &str,
+    content: &str,
+) -> Result<String> {
+    let response = google
+        .send_idempotent(
+            google
+                .as_client()
+                .patch(google.upload_file_url(file_id))
+                .bearer_auth(token)
+                .query(&[
+                    ("uploadType", "media"),
+                    ("fields", "mimeType,headRevisionId"),
+                ])
+                .header("Content-Type", mime_type)
+                .body(content.to_owned()),
+        )
+        .await?;
+    let body = success_text(response).await?;
+    serde_json::from_str::<FileState>(&body)
+        .map_err(|err| Error::deserialise(&err, "FileState", &body))?
+        .to_revision()
 }
 
-pub async fn get_file_metadata(token: &str, file_id: &str) -> Result<String> {
-    let url = format!("https://www.googleapis.com/drive/v3/files/{file_id}");
+/// Returns the metadata of a file, with the given fields in Drive's syntax or
+/// all those sent to the client.
+pub async fn get_file_metadata(
+    google: &GoogleApi,
+    token: &str,
+    file_id: &str,
+    fields: Option<&str>,
+) -> Result<DriveFile> {
+    let response = google
+        .send_idempotent(
+            google
+                .as_client()
+                .get(google.file_url(file_id))
+                .bearer_auth(token)
+                .query(&[("fields", fields.unwrap_or(FILE_FIELDS))]),
+        )
+        .await?;
+    let body = success_text(response).await?;
+    serde_json::from_str(&body).map_err(|err| Error::deserialise(&err, "DriveFile", &body))
+}
+
+/// Renames a file, returning its updated metadata.
+pub async fn rename_file(
+    google: &GoogleApi,
+    token: &str,
+    file_id: &str,
+    name: &str,
+) -> Result<DriveFile> {
+    let response = google
+        .send_idempotent(
+            google
+                .as_client()
+                .patch(google.file_url(file_id))
+                .bearer_auth(token)
+                .query(&[("fields", FILE_FIELDS)])
+                .json(&json!({ "name": name })),
+        )
+        .await?;
+    let body = success_text(response).await?;
+    serde_json::from_str(&body).map_err(|err| Error::deserialise(&err, "DriveFile", &body))
+}
+
+/// Moves a file from its current folders to another one, returning its
+/// updated metadata.
+pub async fn move_file(
+    google: &GoogleApi,
+    token: &str,
+    file_id: &str,
+    from: &[String],
+    to: &str,
+) -> Result<DriveFile> {
+    let response = google
+        .send_idempotent(
+            google
+                .as_client()
+                .patch(google.file_url(file_id))
+                .bearer_auth(token)
+                .query(&[
+                    ("addParents", to),
+                    ("removeParents", &from.join(",")),
+                    ("fields", FILE_FIELDS),
+                ])
+                .json(&json!({})),
+        )
+        .await?;
+    let body = success_text(response).await?;
+    serde_json::from_str(&body).map_err(|err| Error::deserialise(&err, "DriveFile", &body))
+}
+
+/// Moves a file to the trash, or restores it from there, returning its
+/// updated metadata.
+pub async fn set_trashed(
+    google: &GoogleApi,
+    token: &str,
+    file_id: &str,
+    trashed: bool,
+) -> Result<DriveFile> {
+    let response = google
+        .send_idempotent(
+            google
+                .as_client()
+                .patch(google.file_url(file_id))
+                .bearer_auth(token)
+                .query(&[("fields", FILE_FIELDS)])
+                .json(&json!({ "trashed": trashed })),
+        )
+        .await?;
+    let body = success_text(response).await?;
+    serde_json::from_str(&body).map_err(|err| Error::deserialise(&err, "DriveFile", &body))
+}
+
+#[derive(Deserialize)]
+struct FileParents {
+    #[serde(default)]
+    parents: Vec<String>,
+}
+
+/// Returns the ids of the folders containing a file, empty for the root of a
+/// Drive and the files shared with the user.
+pub async fn get_file_parents(
+    google: &GoogleApi,
+    token: &str,
+    file_id: &str,
+) -> Result<Vec<String>> {
+    let response = google
+        .send_idempotent(
+            google
+                .as_client()
+                .get(google.file_url(file_id))
+                .bearer_auth(token)
+                .query(&[("fields", "parents")]),
+        )
+        .await?;
+    let body = success_text(response).await?;
+    serde_json::from_str::<FileParents>(&body)
+        .map(|file| file.parents)
+        .map_err(|err| Error::deserialise(&err, "FileParents", &body))
+}
 
-    match Client::new().get(&url).bearer_auth(token).send().await {
-        Ok(res) => match res.text().await {
-            Ok(text) => Ok(text), // Contains file name and MIME type
-            Err(err) => Err(format!("Failed to get text: {err}")),
-        },
-        Err(err) => Err(format!("Failed to fetch metadata: {err}")),
+/// Deletes a file permanently, skipping the trash.
+///
+/// A file missing on a retry counts as deleted, since an earlier attempt may
+/// have deleted it before its answer was lost.
+pub async fn delete_file(google: &GoogleApi, token: &str, file_id: &str) -> Result<()> {
+    let (response, attempts) = google
+        .send_counting_attempts(
+            google
+                .as_client()
+                .delete(google.file_url(file_id))
+                .bearer_auth(token),
+        )
+        .await?;
+    if response.status().is_success()
+        || (response.status() == StatusCode::NOT_FOUND && attempts > 1)
+    {
+        Ok(())
+    } else {
+        Err(error_from(response).await)
     }
 }
 
-pub async fn folder_contents(token: &str, folder_id: &str) -> Result<DriveFileList> {
-    load_files(&[("q", &format!("'{folder_id}' in parents"))], token).await
+pub async fn folder_contents(
+    google: &GoogleApi,
+    token: &str,
+    folder_id: &str,
+    page: &PageQuery,
+) -> Result<DriveFileList> {
+    load_page(google, token, Query::new().parent_in(folder_id).not_trashed(), page).await
+}
+
+/// Lists the trashed files of a folder and of its subfolders.
+///
+/// The folders are queried in groups of [`MAX_PARENTS_PER_QUERY`], so a page
+/// token is the index of a group, then Drive's token in that group.
+pub async fn folder_trash(
+    google: &GoogleApi,
+    token: &str,
+    folder_id: &str,
+    page: &PageQuery,
+) -> Result<DriveFileList> {
+    let folders = subtree_folders(google, token, folder_id).await?;
+    let groups: Vec<&[String]> = folders.chunks(MAX_PARENTS_PER_QUERY).collect();
+    let trashed_in = |parents: &[String]| Query::new().parent_in_any(parents).trashed();
+    if page.page_token.is_none() && page.as_page_size().is_none() {
+        let mut list = DriveFileList::default();
+        for parents in groups {
+            list.extend(load_page(google, token, trashed_in(parents), page).await?);
+        }
+        return Ok(list);
+    }
+
+    let (group, group_token) = match page.page_token.as_deref() {
+        Some(page_token) => {
+            let (group, group_token) = page_token
+                .split_once(':')
+                .and_then(|(group, rest)| Some((group.parse::<usize>().ok()?, rest)))
+                .ok_or_else(|| Error::BadRequest("Invalid page token.".to_owned()))?;
+            (group, Some(group_token).filter(|rest| !rest.is_empty()))
+        }
+        None => (0, None),
+    };
+    let parents = groups
+        .get(group)
+        .ok_or_else(|| Error::BadRequest("Invalid page token.".to_owned()))?;
+    let group_page = PageQuery { page_token: group_token.map(str::to_owned), ..page.clone() };
+    let mut list = load_page(google, token, trashed_in(parents), &group_page).await?;
+    let next_group = group.saturating_add(1);
+    list.nextPageToken = list.nextPageToken.take().map_or_else(
+        || (next_group < groups.len()).then(|| format!("{next_group}:")),
+        |next| Some(format!("{group}:{next}")),
+    );
+    Ok(list)
+}
+
+/// Loads the requested page of the files matching a query, or every page if
+/// none is requested.
+async fn load_page(
+    google: &GoogleApi,
+    token: &str,
+    search: Query,
+    page: &PageQuery,
+) -> Result<DriveFileList> {
+    let search_string = page.to_query(search)?.to_string();
+    let order_by = page.to_order()?.map(|order| order.to_drive());
+    let fields = page.to_list_fields();
+    let mut query = vec![("q", search_string.as_str()), ("fields", fields.as_str())];
+    if let Some(order) = &order_by {
+        query.push(("orderBy", order));
+    }
+    if page.page_token.is_none() && page.as_page_size().is_none() {
+        return Ok(filter_names(load_all_files(google, &query, token).await?, page));
+    }
+    let page_size = page.as_page_size().map(|size| size.to_string());
+    if let Some(size) = &page_size {
+        query.push(("pageSize", size));
+    }
+    if let Some(page_token) = &page.page_token {
+        query.push(("pageToken", page_token));
+    }
+    Ok(filter_names(load_files(google, &query, token).await?, page))
+}
+
+/// Keeps the files whose name starts with the requested prefix, as Drive also
+/// returns those having it at the start of another word.
+fn filter_names(mut list: DriveFileList, page: &PageQuery) -> DriveFileList {
+    list.files.retain(|file| page.is_name_matching(&file.name));
+    list
+}
+
+/// Searches the direct children of a folder, every filter being applied by
+/// Drive.
+pub async fn search_folder(
+    google: &GoogleApi,
+    token: &str,
+    folder_id: &str,
+    search: &SearchQuery,
+) -> Result<DriveFileList> {
+    let query = search.to_query(folder_id)?.to_string();
+    let fields = PageQuery::default().to_list_fields();
+    load_all_files(google, &[("q", &query), ("fields", &fields)], token).await
 }
@@ -1,11 +1,25 @@
-use super::interface::DriveFile;
-use crate::google::drive::interface::{FileType, create_folder, root_contains_file};
+use alloc::sync::Arc;
+use core::time::Duration;
+use std::sync::OnceLock;
+
+use actix_web::rt::spawn;
+
+use super::sync::{MetadataCache, run_worker};
+use crate::api::GoogleApi;
+use crate::error::{Error, Result};
+use crate::google::drive::interface::{
+    FileType, create_folder, get_file_parents, root_contains_file
+};
 use crate::log;
 
+/// Deepest folder nesting searched for the app folder among the ancestors of
+/// a file.
+const MAX_FOLDER_DEPTH: usize = 32;
+
 #[derive(Debug)]
 enum AppFolder {
     Name(String),
-    Info(DriveFile),
+    Id(Box<str>),
 }
 
 impl AppFolder {
@@ -17,31 +31,80 @@ impl AppFolder {
 #[derive(Debug)]
 pub struct DriveManager {
     app_folder: async_lock::Mutex<AppFolder>,
+    /// Set once the sync of the app folder is started.
+    cache: OnceLock<MetadataCache>,
 }
 
 impl DriveManager {
     pub const fn new(folder_name: String) -> Self {
-        Self { app_folder: async_lock::Mutex::new(AppFolder::Name(folder_name)) }
+        Self {
+            app_folder: async_lock::Mutex::new(AppFolder::Name(folder_name)),
+            cache: OnceLock::new(),
+        }
+    }
+
+    pub fn as_cache(&self) -> Option<&MetadataCache> {
+        self.cache.get()
     }
 
-    pub async fn app_folder_id(&self, token: &str) -> Result<Box<str>, String> {
+    /// Hands the latest token of the session to the background sync of the
+    /// app folder, starting it if it isn't running.
+    pub fn start_sync(
+        self: &Arc<Self>,
+        google: &GoogleApi,
+        token: &str,
+        interval: Duration,
+    ) -> Result<()> {
+        let cache = self.cache.get_or_init(|| MetadataCache::new(interval));
+        cache.set_token(token)?;
+        if cache.claim_worker() {
+            spawn(run_worker(Arc::downgrade(self), google.clone()));
+        }
+        Ok(())
+    }
+
+    pub async fn app_folder_id(&self, google: &GoogleApi, token: &str) -> Result<Box<str>> {
         let mut app_folder = self.app_folder.lock().await;
         Ok(match app_folder.inner() {
-            AppFolder::Info(folder) => folder.to_id(),
+            AppFolder::Id(id) => id.clone(),
             AppFolder::Name(name) => {
                 log!("App folder id not loaded");
                 let folder = if let Some(folder) =
-                    root_contains_file(token, name, &FileType::Folder).await?
+                    root_contains_file(google, token, name, &FileType::Folder).await?
                 {
                     folder
                 } else {
                     log!("App folder doesn't exist");
-                    create_folder(token, name).await?
+                    create_folder(google, token, name, None).await?
                 };
                 let id = folder.to_id();
-                *app_folder = AppFolder::Info(folder);
+                *app_folder = AppFolder::Id(id.clone());
                 id
             }
         })
     }
+
+    /// Checks that a file is inside the app folder, at any depth, so that
+    /// the other files of the user's Drive can't be touched through its id.
+    pub async fn ensure_in_app_folder(
+        &self,
+        google: &GoogleApi,
+        token: &str,
+        file_id: &str,
+    ) -> Result<()> {
+        let app_folder_id = self.app_folder_id(google, token).await?;
+        let mut current = file_id.to_owned();
+        for _ in 0..MAX_FOLDER_DEPTH {
+            let parents = get_file_parents(google, token, &current).await?;
+            if parents.iter().any(|parent| **parent == *app_folder_id) {
+                return Ok(());
+            }
+            // Drive only allows one parent per file.
+            let Some(parent) = parents.into_iter().next() else {
+                break;
+            };
+            current = parent;
+        }
+        Err(Error::NotFound(format!("File {file_id} not found in the app folder.")))
+    }
 }
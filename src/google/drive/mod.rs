@@ -1,28 +1,96 @@
-mod action;
-mod interface;
+pub mod action;
+mod changes;
+mod diff;
+mod full_text;
+pub mod index;
+pub mod interface;
 pub mod manager;
+mod query;
+pub mod sync;
+mod tree;
 
 use actix_web::{HttpRequest, HttpResponse, web};
-use interface::folder_contents;
+use full_text::search_notes;
+use interface::{PageQuery, SearchQuery, search_folder};
+use serde_json::json;
+use tree::{TreeQuery, load_tree};
 
-use crate::state::{AppData, ok_or_internal};
-use crate::{token, unwrap_return_internal};
+use crate::error::Error;
+use crate::state::{AppData, ok_or_error};
+use crate::storage::NoteStorage as _;
+use crate::with_storage;
 
 #[actix_web::get("/ls")]
-async fn ls(req: HttpRequest, data: AppData) -> HttpResponse {
-    let token = token!(data, req);
-    ok_or_internal(
-        folder_contents(
-            token,
-            &unwrap_return_internal!(data.as_drive().app_folder_id(token).await),
+async fn ls(req: HttpRequest, data: AppData, page: web::Query<PageQuery>) -> HttpResponse {
+    ok_or_error(with_storage!(data, req, |storage| {
+        serde_json::to_string_pretty(&storage.list(&page).await?)
+            .map_err(|err| Error::Internal(err.to_string()))
+    }))
+}
+
+/// Lists the trashed notes of the app folder.
+#[actix_web::get("/trash")]
+async fn trash(req: HttpRequest, data: AppData, page: web::Query<PageQuery>) -> HttpResponse {
+    ok_or_error(with_storage!(data, req, |storage| {
+        serde_json::to_string_pretty(&storage.list_trash(&page).await?)
+            .map_err(|err| Error::Internal(err.to_string()))
+    }))
+}
+
+/// Lists the nested folders and notes of the app folder, for a sidebar.
+#[actix_web::get("/tree")]
+async fn get_tree(req: HttpRequest, data: AppData, query: web::Query<TreeQuery>) -> HttpResponse {
+    ok_or_error(with_storage!(data, req, |storage| {
+        let drive = storage.as_drive()?;
+        let folder_id = drive.to_folder_id(query.as_folder()).await?;
+        let cached = match drive.to_synced_cache().await? {
+            Some(cache) => cache.tree(&folder_id, query.to_depth())?,
+            None => None,
+        };
+        let files = match cached {
+            Some(files) => files,
+            None =>
+                load_tree(drive.as_google(), drive.as_token(), &folder_id, query.to_depth()).await?,
+        };
+        serde_json::to_string_pretty(&json!({ "id": folder_id, "files": files }))
+            .map_err(|err| Error::Internal(err.to_string()))
+    }))
+}
+
+/// Searches the text of `q` in the notes of the app folder, or else the files
+/// of the app folder matching the other criteria.
+#[actix_web::get("/search")]
+async fn search(req: HttpRequest, data: AppData, search: web::Query<SearchQuery>) -> HttpResponse {
+    ok_or_error(with_storage!(data, req, |storage| {
+        let drive = storage.as_drive()?;
+        let folder_id = drive.app_folder_id().await?;
+        if let Some(text) = search.as_text() {
+            let results = match data.as_search_index() {
+                Some(index) => {
+                    index
+                        .sync(drive.as_google(), drive.as_token(), &folder_id)
+                        .await?;
+                    index.search(&folder_id, text, &search)?
+                }
+                None =>
+                    search_notes(drive.as_google(), drive.as_token(), &folder_id, text, &search)
+                        .await?,
+            };
+            return serde_json::to_string_pretty(&results)
+                .map_err(|err| Error::Internal(err.to_string()));
+        }
+        serde_json::to_string_pretty(
+            &search_folder(drive.as_google(), drive.as_token(), &folder_id, &search).await?,
         )
-        .await
-        .map(|drivelist| serde_json::to_string_pretty(&drivelist).unwrap()),
-    )
+        .map_err(|err| Error::Internal(err.to_string()))
+    }))
 }
 
 pub fn drive_config(cfg: &mut web::ServiceConfig) {
     cfg //
         .service(ls)
+        .service(search)
+        .service(trash)
+        .service(get_tree)
         .service(web::scope("/action").configure(action::config));
 }
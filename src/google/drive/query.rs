@@ -0,0 +1,97 @@
+use core::fmt;
+
+use super::interface::{FileType, MARKDOWN_MIME_TYPE};
+
+/// Builder of Drive's search syntax, used as the `q` parameter of file
+/// listings.
+///
+/// The clauses are joined with `and`, and the values are quoted and escaped,
+/// so names containing `'` or `\` are searched as is.
+#[derive(Default)]
+pub struct Query {
+    clauses: Vec<String>,
+}
+
+impl Query {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn with(mut self, clause: String) -> Self {
+        self.clauses.push(clause);
+        self
+    }
+
+    pub fn name_eq(self, name: &str) -> Self {
+        self.with(format!("name = {}", quote(name)))
+    }
+
+    pub fn mime_type_eq(self, mime_type: &str) -> Self {
+        self.with(format!("mimeType = {}", quote(mime_type)))
+    }
+
+    pub fn file_type(self, filetype: &FileType) -> Self {
+        self.mime_type_eq(&filetype.as_mime_type())
+    }
+
+    pub fn parent_in(self, folder_id: &str) -> Self {
+        self.with(format!("{} in parents", quote(folder_id)))
+    }
+
+    /// Keeps the files having at least one of the folders as parent.
+    pub fn parent_in_any(self, folder_ids: &[String]) -> Self {
+        let parents: Vec<_> = folder_ids
+            .iter()
+            .map(|folder_id| format!("{} in parents", quote(folder_id)))
+            .collect();
+        self.with(format!("({})", parents.join(" or ")))
+    }
+
+    /// Keeps the notes: Google Docs and Markdown files.
+    pub fn note(self) -> Self {
+        self.with(format!(
+            "(mimeType = {} or mimeType = {})",
+            quote(&FileType::Document.as_mime_type()),
+            quote(MARKDOWN_MIME_TYPE)
+        ))
+    }
+
+    pub fn not_trashed(self) -> Self {
+        self.with("trashed = false".to_owned())
+    }
+
+    pub fn trashed(self) -> Self {
+        self.with("trashed = true".to_owned())
+    }
+
+    pub fn starred(self) -> Self {
+        self.with("starred = true".to_owned())
+    }
+
+    /// Keeps the files having a word of their name starting with the text,
+    /// whatever the case, which is what `contains` means for names in Drive.
+    pub fn name_contains(self, prefix: &str) -> Self {
+        self.with(format!("name contains {}", quote(prefix)))
+    }
+
+    /// Keeps the files modified after the given RFC 3339 date-time (e.g.
+    /// `2025-01-31T12:00:00Z`).
+    pub fn modified_after(self, time: &str) -> Self {
+        self.with(format!("modifiedTime > {}", quote(time)))
+    }
+
+    pub fn full_text_contains(self, text: &str) -> Self {
+        self.with(format!("fullText contains {}", quote(text)))
+    }
+}
+
+impl fmt::Display for Query {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.clauses.join(" and ").fmt(f)
+    }
+}
+
+/// Quotes a value for Drive's search syntax.
+fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+}
@@ -0,0 +1,378 @@
+use alloc::sync::Weak;
+use core::sync::atomic::{AtomicBool, Ordering};
+use core::time::Duration;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use actix_web::rt::time::sleep;
+
+use super::changes::{get_start_page_token, list_changes};
+use super::full_text::MAX_PARENTS_PER_QUERY;
+use super::interface::{FILE_FIELDS, FileType, PageQuery, load_all_files};
+use super::manager::DriveManager;
+use super::query::Query;
+use super::tree::{TreeNode, build_tree};
+use crate::api::GoogleApi;
+use crate::error::Result;
+use crate::log;
+use crate::state::unlock;
+use crate::storage::{NoteList, NoteMetadata};
+
+/// Intervals without request after which the worker stops, until the next
+/// request of the session.
+const MAX_IDLE_INTERVALS: u32 = 10;
+
+fn is_folder(file: &NoteMetadata) -> bool {
+    file.mime_type == FileType::Folder.as_mime_type()
+}
+
+/// Files of the app folder tree, as of a page token of the changes feed.
+#[derive(Debug)]
+struct CacheState {
+    app_folder_id: Box<str>,
+    changes_token: String,
+    /// Files of the app folder and its subfolders, folders included, by id.
+    files: HashMap<String, NoteMetadata>,
+    /// Missing if this server changed files since.
+    synced_at: Option<Instant>,
+}
+
+impl CacheState {
+    fn is_known_folder(&self, folder_id: &str) -> bool {
+        folder_id == &*self.app_folder_id || self.files.get(folder_id).is_some_and(is_folder)
+    }
+
+    fn is_inside(&self, file: &NoteMetadata) -> bool {
+        file.parents
+            .iter()
+            .any(|parent| self.is_known_folder(parent))
+    }
+
+    /// Removes a file, and all the files below it if it is a folder.
+    fn remove_tree(&mut self, id: &str) {
+        let mut removed = vec![id.to_owned()];
+        while let Some(current) = removed.pop() {
+            if self.files.remove(&current).is_some() {
+                removed.extend(
+                    self.files
+                        .values()
+                        .filter(|file| file.parents.contains(&current))
+                        .map(|file| file.id.clone()),
+                );
+            }
+        }
+    }
+}
+
+/// Metadata of the app folder tree of a session, kept up to date from Drive's
+/// changes feed by a background worker, so that the listings are served from
+/// memory.
+///
+/// If the worker falls behind, e.g. while the session's token is expired or
+/// after it stopped for want of requests, or if this server changed files,
+/// the next request syncs first.
+#[derive(Debug)]
+pub struct MetadataCache {
+    interval: Duration,
+    /// Latest access token of the session, for the worker.
+    token: Mutex<Option<Box<str>>>,
+    /// Time of the latest request of the session.
+    used_at: Mutex<Instant>,
+    is_worker_running: AtomicBool,
+    /// Missing until the first sync.
+    state: Mutex<Option<CacheState>>,
+    /// Held while the cache is loaded or updated from the changes feed.
+    sync_lock: async_lock::Mutex<()>,
+}
+
+impl MetadataCache {
+    pub fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            token: Mutex::new(None),
+            used_at: Mutex::new(Instant::now()),
+            is_worker_running: AtomicBool::new(false),
+            state: Mutex::new(None),
+            sync_lock: async_lock::Mutex::new(()),
+        }
+    }
+
+    /// Hands the latest token of the session to the worker, with the time of
+    /// the request.
+    pub fn set_token(&self, token: &str) -> Result<()> {
+        *unlock(&self.token, "sync token")? = Some(token.into());
+        *unlock(&self.used_at, "session use")? = Instant::now();
+        Ok(())
+    }
+
+    /// Marks the worker as running, returning whether it has to be spawned.
+    pub fn claim_worker(&self) -> bool {
+        self.is_worker_running
+            .compare_exchange(false, true, Ordering::AcqRel, Ordering::Acquire)
+            .is_ok()
+    }
+
+    fn is_idle(&self) -> Result<bool> {
+        Ok(unlock(&self.used_at, "session use")?.elapsed()
+            > self.interval.saturating_mul(MAX_IDLE_INTERVALS))
+    }
+
+    /// Makes the next request sync first, after this server changed files.
+    pub fn mark_stale(&self) -> Result<()> {
+        if let Some(state) = unlock(&self.state, "metadata cache")?.as_mut() {
+            state.synced_at = None;
+        }
+        Ok(())
+    }
+
+    fn is_fresh(&self) -> Result<bool> {
+        Ok(unlock(&self.state, "metadata cache")?
+            .as_ref()
+            .and_then(|state| state.synced_at)
+            .is_some_and(|synced_at| synced_at.elapsed() < self.interval.saturating_mul(2)))
+    }
+
+    /// Syncs the cache, unless the worker did recently.
+    pub async fn ensure_synced(
+        &self,
+        google: &GoogleApi,
+        token: &str,
+        app_folder_id: &str,
+    ) -> Result<()> {
+        let _guard = self.sync_lock.lock().await;
+        if self.is_fresh()? {
+            return Ok(());
+        }
+        self.sync_locked(google, token, app_folder_id).await
+    }
+
+    async fn sync(&self, google: &GoogleApi, token: &str, app_folder_id: &str) -> Result<()> {
+        let _guard = self.sync_lock.lock().await;
+        self.sync_locked(google, token, app_folder_id).await
+    }
+
+    async fn sync_locked(
+        &self,
+        google: &GoogleApi,
+        token: &str,
+        app_folder_id: &str,
+    ) -> Result<()> {
+        let changes_token = unlock(&self.state, "metadata cache")?
+            .as_ref()
+            .filter(|state| &*state.app_folder_id == app_folder_id)
+            .map(|state| state.changes_token.clone());
+        match changes_token {
+            Some(since) => self.apply_changes(google, token, &since).await,
+            None => self.load(google, token, app_folder_id).await,
+        }
+    }
+
+    /// Loads the metadata of every file of the app folder tree.
+    async fn load(&self, google: &GoogleApi, token: &str, app_folder_id: &str) -> Result<()> {
+        log!("Loading the metadata of the app folder...");
+        // Taken first, so that the changes made while loading are replayed.
+        let changes_token = get_start_page_token(google, token).await?;
+        let files = load_subtree(google, token, app_folder_id).await?;
+        *unlock(&self.state, "metadata cache")? = Some(CacheState {
+            app_folder_id: app_folder_id.into(),
+            changes_token,
+            files: files
+                .into_iter()
+                .map(|file| (file.id.clone(), file))
+                .collect(),
+            synced_at: Some(Instant::now()),
+        });
+        Ok(())
+    }
+
+    /// Applies the changes made since the last sync, loading the contents of
+    /// the folders moved into the app folder tree.
+    async fn apply_changes(&self, google: &GoogleApi, token: &str, since: &str) -> Result<()> {
+        let (changes, new_token) = list_changes(google, token, since).await?;
+        let mut new_folders = Vec::new();
+        if let Some(state) = unlock(&self.state, "metadata cache")?.as_mut() {
+            for change in changes {
+                let id = change.as_file_id().to_owned();
+                match change.into_file() {
+                    Some(file) if state.is_inside(&file) => {
+                        if is_folder(&file) && !state.files.contains_key(&id) {
+                            new_folders.push(id.clone());
+                        }
+                        state.files.insert(id, file);
+                    }
+                    // Deleted, trashed or moved out.
+                    _ => state.remove_tree(&id),
+                }
+            }
+        }
+        let mut moved_in = Vec::new();
+        for folder_id in new_folders {
+            moved_in.extend(load_subtree(google, token, &folder_id).await?);
+        }
+        if let Some(state) = unlock(&self.state, "metadata cache")?.as_mut() {
+            state
+                .files
+                .extend(moved_in.into_iter().map(|file| (file.id.clone(), file)));
+            state.changes_token = new_token;
+            state.synced_at = Some(Instant::now());
+        }
+        Ok(())
+    }
+
+    /// Lists the files of a folder from memory, with the order and filters of
+    /// the page.
+    ///
+    /// Returns `None` for the listings the cache can't serve: unknown folders,
+    /// selected fields and page tokens from Drive.
+    pub fn list(&self, folder_id: &str, page: &PageQuery) -> Result<Option<NoteList>> {
+        let Ok(start) = page.as_page_token().map_or(Ok(0), str::parse::<usize>) else {
+            return Ok(None);
+        };
+        if page.is_selecting_fields() {
+            return Ok(None);
+        }
+        let order = page.to_order()?;
+        let file_type = page
+            .to_file_type()?
+            .map(|file_type| file_type.as_mime_type());
+        let state = unlock(&self.state, "metadata cache")?;
+        let Some(cache) = state
+            .as_ref()
+            .filter(|cache| cache.is_known_folder(folder_id))
+        else {
+            return Ok(None);
+        };
+        let mut files: Vec<&NoteMetadata> = cache
+            .files
+            .values()
+            .filter(|file| file.parents.iter().any(|parent| parent == folder_id))
+            .filter(|file| {
+                file_type
+                    .as_ref()
+                    .is_none_or(|mime_type| file.mime_type == *mime_type)
+            })
+            .filter(|file| !page.is_starred_only() || file.starred == Some(true))
+            .filter(|file| page.is_name_matching(&file.name))
+            .collect();
+        // By name unless another order is requested, as Drive's is unspecified.
+        files.sort_by(|left, right| {
+            order
+                .as_ref()
+                .map_or_else(|| left.name.cmp(&right.name), |sort| sort.compare(left, right))
+                .then_with(|| left.id.cmp(&right.id))
+        });
+        let end = page
+            .as_page_size()
+            .map_or(files.len(), |size| start.saturating_add(size.into()).min(files.len()));
+        let next_page_token = (end < files.len()).then(|| end.to_string());
+        let listed = files
+            .get(start.min(end)..end)
+            .unwrap_or_default()
+            .iter()
+            .map(|file| (*file).clone())
+            .collect();
+        drop(state);
+        Ok(Some(NoteList {
+            files: listed,
+            incomplete_search: false,
+            kind: "drive#fileList".to_owned(),
+            next_page_token,
+        }))
+    }
+
+    /// Returns the tree of a folder from memory, or `None` if the folder
+    /// isn't known.
+    pub fn tree(&self, folder_id: &str, depth: u8) -> Result<Option<Vec<TreeNode>>> {
+        let state = unlock(&self.state, "metadata cache")?;
+        Ok(state
+            .as_ref()
+            .filter(|cache| cache.is_known_folder(folder_id))
+            .map(|cache| {
+                let files: Vec<&NoteMetadata> = cache.files.values().collect();
+                build_tree(&files, folder_id, depth)
+            }))
+    }
+}
+
+/// Lists the metadata of the untrashed files below a folder, level by level.
+async fn load_subtree(
+    google: &GoogleApi,
+    token: &str,
+    folder_id: &str,
+) -> Result<Vec<NoteMetadata>> {
+    let fields = format!("nextPageToken,files({FILE_FIELDS})");
+    let mut files = Vec::new();
+    let mut level = vec![folder_id.to_owned()];
+    while !level.is_empty() {
+        let mut subfolders = Vec::new();
+        for parents in level.chunks(MAX_PARENTS_PER_QUERY) {
+            let query = Query::new()
+                .parent_in_any(parents)
+                .not_trashed()
+                .to_string();
+            let found = NoteList::from(
+                load_all_files(google, &[("q", &query), ("fields", &fields)], token).await?,
+            )
+            .files;
+            subfolders.extend(
+                found
+                    .iter()
+                    .filter(|file| is_folder(file))
+                    .map(|file| file.id.clone()),
+            );
+            files.extend(found);
+        }
+        level = subfolders;
+    }
+    Ok(files)
+}
+
+/// Syncs the cache of a session every interval, until the session expires or
+/// has had no request for [`MAX_IDLE_INTERVALS`].
+///
+/// Without a valid token, e.g. when the session's token expired, it waits for
+/// the next request to bring a new one.
+pub async fn run_worker(drive: Weak<DriveManager>, google: GoogleApi) {
+    loop {
+        let Some(interval) = drive
+            .upgrade()
+            .and_then(|manager| manager.as_cache().map(|cache| cache.interval))
+        else {
+            return;
+        };
+        sleep(interval).await;
+        let Some(manager) = drive.upgrade() else {
+            return;
+        };
+        let Some(cache) = manager.as_cache() else {
+            return;
+        };
+        if cache.is_idle().unwrap_or(true) {
+            cache.is_worker_running.store(false, Ordering::Release);
+            return;
+        }
+        let latest_token = match unlock(&cache.token, "sync token") {
+            Ok(latest) => latest.clone(),
+            Err(err) => {
+                log!("{err}");
+                return;
+            }
+        };
+        let Some(token) = latest_token else {
+            continue;
+        };
+        let synced = match manager.app_folder_id(&google, &token).await {
+            Ok(app_folder_id) => cache.sync(&google, &token, &app_folder_id).await,
+            Err(err) => Err(err),
+        };
+        if let Err(err) = synced {
+            log!("Failed to sync the app folder:\n{err}");
+            if err.is_unauthorised()
+                && let Ok(mut expired) = unlock(&cache.token, "sync token")
+            {
+                *expired = None;
+            }
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use async_lock::Semaphore;
+use futures_util::future::{LocalBoxFuture, try_join_all};
+use serde::{Deserialize, Serialize};
+
+use super::interface::{FileType, MAX_PAGE_SIZE};
+use super::query::Query;
+use crate::api::GoogleApi;
+use crate::error::{Error, Result};
+use crate::storage::NoteMetadata;
+
+/// Fields of the files of the tree, in Drive's syntax.
+const TREE_FIELDS: &str = "nextPageToken,files(id,name,mimeType,modifiedTime,size)";
+
+/// Folders listed at the same time while the tree is loaded.
+const MAX_CONCURRENT_LISTINGS: usize = 4;
+
+/// Deepest level of folders loaded, whatever the client asks.
+const MAX_DEPTH: u8 = 10;
+
+#[derive(Deserialize)]
+pub struct TreeQuery {
+    /// Levels of folders to load, 1 for the direct children only.
+    depth: Option<u8>,
+    /// Folder at the root of the tree, the app folder if none is given.
+    folder: Option<String>,
+}
+
+impl TreeQuery {
+    pub fn as_folder(&self) -> Option<&str> {
+        self.folder.as_deref()
+    }
+
+    pub fn to_depth(&self) -> u8 {
+        self.depth.unwrap_or(MAX_DEPTH).clamp(1, MAX_DEPTH)
+    }
+}
+
+/// Note or folder of the tree.
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TreeNode {
+    id: String,
+    name: String,
+    mime_type: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    modified_time: Option<String>,
+    /// Size in bytes, as the string Drive sends for 64-bit integers, missing
+    /// for Google Docs and folders.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    size: Option<String>,
+    /// Contents of a folder, missing if the depth limit was reached.
+    #[serde(skip_deserializing, skip_serializing_if = "Option::is_none")]
+    children: Option<Vec<Self>>,
+}
+
+impl TreeNode {
+    fn new(file: &NoteMetadata) -> Self {
+        Self {
+            id: file.id.clone(),
+            name: file.name.clone(),
+            mime_type: file.mime_type.clone(),
+            modified_time: file.modified_time.clone(),
+            size: file.size.clone(),
+            children: None,
+        }
+    }
+
+    fn is_folder(&self) -> bool {
+        self.mime_type == FileType::Folder.as_mime_type()
+    }
+}
+
+/// Sorts the folders first, like in a file browser, then by name.
+fn sort_nodes(nodes: &mut [TreeNode]) {
+    nodes.sort_by(|left, right| {
+        right
+            .is_folder()
+            .cmp(&left.is_folder())
+            .then_with(|| left.name.cmp(&right.name))
+    });
+}
+
+/// Builds the tree of a folder from the files already loaded, at any depth.
+pub fn build_tree(files: &[&NoteMetadata], folder_id: &str, depth: u8) -> Vec<TreeNode> {
+    let mut children: Vec<TreeNode> = files
+        .iter()
+        .filter(|file| file.parents.iter().any(|parent| parent == folder_id))
+        .map(|file| TreeNode::new(file))
+        .collect();
+    sort_nodes(&mut children);
+    if depth > 1 {
+        for folder in children.iter_mut().filter(|child| child.is_folder()) {
+            folder.children = Some(build_tree(files, &folder.id, depth.saturating_sub(1)));
+        }
+    }
+    children
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct TreePage {
+    #[serde(default)]
+    files: Vec<TreeNode>,
+    next_page_token: Option<String>,
+}
+
+/// Loads the nested folders and notes of a folder.
+///
+/// The folders of a level are listed concurrently, but never more than
+/// [`MAX_CONCURRENT_LISTINGS`] at once across the whole tree.
+pub async fn load_tree(
+    google: &GoogleApi,
+    token: &str,
+    folder_id: &str,
+    depth: u8,
+) -> Result<Vec<TreeNode>> {
+    TreeLoader { google, token, permits: Semaphore::new(MAX_CONCURRENT_LISTINGS) }
+        .load(folder_id.to_owned(), depth)
+        .await
+}
+
+struct TreeLoader<'req> {
+    google: &'req GoogleApi,
+    token: &'req str,
+    permits: Semaphore,
+}
+
+impl TreeLoader<'_> {
+    /// Boxed, as the loading of a folder awaits the loading of its
+    /// subfolders.
+    fn load(&self, folder_id: String, depth: u8) -> LocalBoxFuture<'_, Result<Vec<TreeNode>>> {
+        Box::pin(async move {
+            let mut children = {
+                let _permit = self.permits.acquire().await;
+                self.list_children(&folder_id).await?
+            };
+            sort_nodes(&mut children);
+            if depth > 1 {
+                let subtrees = try_join_all(
+                    children
+                        .iter()
+                        .filter(|child| child.is_folder())
+                        .map(|folder| self.load(folder.id.clone(), depth.saturating_sub(1))),
+                )
+                .await?;
+                for (folder, subtree) in children
+                    .iter_mut()
+                    .filter(|child| child.is_folder())
+                    .zip(subtrees)
+                {
+                    folder.children = Some(subtree);
+                }
+            }
+            Ok(children)
+        })
+    }
+
+    /// Lists every page of the untrashed children of a folder.
+    async fn list_children(&self, folder_id: &str) -> Result<Vec<TreeNode>> {
+        let query = Query::new().parent_in(folder_id).not_trashed().to_string();
+        let page_size = MAX_PAGE_SIZE.to_string();
+        let mut files = Vec::new();
+        let mut page_token: Option<String> = None;
+        loop {
+            let mut params = vec![
+                ("q", query.as_str()),
+                ("fields", TREE_FIELDS),
+                ("pageSize", page_size.as_str()),
+            ];
+            if let Some(next_page) = &page_token {
+                params.push(("pageToken", next_page.as_str()));
+            }
+            let body = self
+                .google
+                .send_idempotent_and_text(
+                    self.google
+                        .as_client()
+                        .get(self.google.files_url())
+                        .bearer_auth(self.token)
+                        .query(&params),
+                )
+                .await?;
+            let page: TreePage = serde_json::from_str(&body)
+                .map_err(|err| Error::deserialise(&err, "TreePage", &body))?;
+            files.extend(page.files);
+            match page.next_page_token {
+                Some(next_page) => page_token = Some(next_page),
+                None => return Ok(files),
+            }
+        }
+    }
+}
@@ -0,0 +1,250 @@
+use alloc::sync::Arc;
+use core::fmt;
+use core::time::Duration;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use actix_web::HttpRequest;
+use actix_web::cookie::{Cookie, CookieJar, Key, SameSite};
+use base64::Engine as _;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use rand::RngCore as _;
+
+use crate::error::{Error, Result};
+use crate::google::auth::login::{ClientOAuthData, PendingLogin, RefreshedToken};
+use crate::google::drive::manager::DriveManager;
+use crate::state::unlock;
+
+const SESSION_COOKIE: &str = "md-viewer-session";
+
+/// Number of random bytes in session ids and OAuth secrets.
+const TOKEN_LEN: usize = 32;
+
+/// Time after which an idle session is forgotten, once logged in.
+const LOGGED_IN_TTL: Duration = Duration::from_hours(7 * 24);
+
+/// Time after which an idle session is forgotten if it isn't logged in, long
+/// enough to go through the login.
+const ANONYMOUS_TTL: Duration = Duration::from_mins(15);
+
+/// Least time between two sweeps of the expired sessions.
+const SWEEP_INTERVAL: Duration = Duration::from_mins(1);
+
+/// Generates an unguessable URL-safe string.
+pub fn random_token() -> Box<str> {
+    let mut bytes = [0u8; TOKEN_LEN];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes).into()
+}
+
+/// Data belonging to one browser, identified by its session cookie.
+#[derive(Debug)]
+pub struct Session {
+    callback: Option<String>,
+    client_oauth_data: Option<ClientOAuthData>,
+    drive: Arc<DriveManager>,
+    pending_login: Option<PendingLogin>,
+    last_used: Instant,
+}
+
+impl Session {
+    fn new(app_folder: String) -> Self {
+        Self {
+            callback: None,
+            client_oauth_data: None,
+            drive: Arc::new(DriveManager::new(app_folder)),
+            pending_login: None,
+            last_used: Instant::now(),
+        }
+    }
+
+    fn is_expired(&self) -> bool {
+        let ttl = if self.client_oauth_data.is_some() {
+            LOGGED_IN_TTL
+        } else {
+            ANONYMOUS_TTL
+        };
+        self.last_used.elapsed() > ttl
+    }
+
+    pub const fn as_client_data(&self) -> Option<&ClientOAuthData> {
+        self.client_oauth_data.as_ref()
+    }
+
+    pub fn to_drive(&self) -> Arc<DriveManager> {
+        Arc::clone(&self.drive)
+    }
+
+    pub fn set_callback(&mut self, new_callback: String) {
+        self.callback = Some(new_callback);
+    }
+
+    pub const fn take_callback(&mut self) -> Option<String> {
+        self.callback.take()
+    }
+
+    pub fn set_pending_login(&mut self, pending_login: PendingLogin) {
+        self.pending_login = Some(pending_login);
+    }
+
+    pub const fn take_pending_login(&mut self) -> Option<PendingLogin> {
+        self.pending_login.take()
+    }
+
+    pub const fn take_client_data(&mut self) -> Option<ClientOAuthData> {
+        self.client_oauth_data.take()
+    }
+
+    pub fn set_client_data(&mut self, new_client_data: ClientOAuthData) {
+        self.client_oauth_data = Some(new_client_data);
+    }
+
+    /// Updates the access token, returning the new one if the session is
+    /// still logged in.
+    pub fn refresh_client_data(&mut self, refreshed: RefreshedToken) -> Option<Box<str>> {
+        self.client_oauth_data.as_mut().map(|client_data| {
+            client_data.refresh(refreshed);
+            client_data.as_token().into()
+        })
+    }
+}
+
+/// In-memory sessions, keyed by the id stored in a signed cookie.
+///
+/// The sessions idle for too long are forgotten, along with their Drive data
+/// and sync worker.
+pub struct SessionStore {
+    key: Key,
+    sessions: Mutex<HashMap<Box<str>, Session>>,
+    swept_at: Mutex<Instant>,
+}
+
+impl fmt::Debug for SessionStore {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let count = unlock(&self.sessions, "sessions").map_or(0, |sessions| sessions.len());
+        f.debug_struct("SessionStore")
+            .field("sessions", &count)
+            .finish_non_exhaustive()
+    }
+}
+
+impl SessionStore {
+    pub fn new() -> Self {
+        Self {
+            key: Key::generate(),
+            sessions: Mutex::default(),
+            swept_at: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Returns the id of the request's session, if the cookie is correctly
+    /// signed and the session still exists, marking it as used.
+    pub fn to_id(&self, req: &HttpRequest) -> Result<Option<Box<str>>> {
+        let Some(cookie) = req.cookie(SESSION_COOKIE) else {
+            return Ok(None);
+        };
+        let mut jar = CookieJar::new();
+        jar.add_original(cookie);
+        let Some(signed_cookie) = jar.signed(&self.key).get(SESSION_COOKIE) else {
+            return Ok(None);
+        };
+        let id: Box<str> = signed_cookie.value().into();
+        let mut sessions = unlock(&self.sessions, "sessions")?;
+        let is_live = match sessions.get_mut(&id) {
+            Some(session) if !session.is_expired() => {
+                session.last_used = Instant::now();
+                true
+            }
+            Some(_) => {
+                drop(sessions.remove(&id));
+                false
+            }
+            None => false,
+        };
+        drop(sessions);
+        Ok(is_live.then_some(id))
+    }
+
+    /// Returns the id of the request's session, creating a new one if the
+    /// request has none.
+    pub fn to_or_create_id(&self, req: &HttpRequest, app_folder: &str) -> Result<Box<str>> {
+        self.to_id(req)?
+            .map_or_else(|| self.create(app_folder.to_owned()), Ok)
+    }
+
+    fn create(&self, app_folder: String) -> Result<Box<str>> {
+        self.sweep()?;
+        let id = random_token();
+        unlock(&self.sessions, "sessions")?.insert(id.clone(), Session::new(app_folder));
+        Ok(id)
+    }
+
+    /// Forgets the expired sessions, at most once every [`SWEEP_INTERVAL`].
+    fn sweep(&self) -> Result<()> {
+        let mut swept_at = unlock(&self.swept_at, "session sweep")?;
+        if swept_at.elapsed() < SWEEP_INTERVAL {
+            return Ok(());
+        }
+        *swept_at = Instant::now();
+        drop(swept_at);
+        unlock(&self.sessions, "sessions")?.retain(|_, session| !session.is_expired());
+        Ok(())
+    }
+
+    /// Replaces the session with a fresh one under a new id, to prevent
+    /// session fixation when the user logs in.
+    ///
+    /// Only the callback is kept, as the data of the previous user must not
+    /// leak to the new one.
+    pub fn renew(&self, old_id: &str, app_folder: &str) -> Result<Box<str>> {
+        let callback = self
+            .remove(old_id)?
+            .and_then(|mut session| session.take_callback());
+        let new_id = self.create(app_folder.to_owned())?;
+        if let Some(old_callback) = callback {
+            self.with_session(&new_id, |session| session.set_callback(old_callback))?;
+        }
+        Ok(new_id)
+    }
+
+    pub fn remove(&self, id: &str) -> Result<Option<Session>> {
+        Ok(unlock(&self.sessions, "sessions")?.remove(id))
+    }
+
+    pub fn with_session<T, F>(&self, id: &str, action: F) -> Result<T>
+    where
+        F: FnOnce(&mut Session) -> T,
+    {
+        unlock(&self.sessions, "sessions")?
+            .get_mut(id)
+            .map(action)
+            .ok_or_else(|| Error::Internal(format!("Session {id} not found")))
+    }
+
+    /// Builds a cookie that removes the session cookie from the browser.
+    pub fn to_removal_cookie() -> Cookie<'static> {
+        let mut cookie = Cookie::build(SESSION_COOKIE, "")
+            .path("/")
+            .secure(true)
+            .finish();
+        cookie.make_removal();
+        cookie
+    }
+
+    /// Builds the signed cookie that identifies the session with the given id.
+    pub fn to_cookie(&self, id: &str) -> Cookie<'static> {
+        let mut jar = CookieJar::new();
+        jar.signed_mut(&self.key).add(
+            Cookie::build(SESSION_COOKIE, id.to_owned())
+                .path("/")
+                .http_only(true)
+                .secure(true)
+                .same_site(SameSite::Lax)
+                .finish(),
+        );
+        jar.get(SESSION_COOKIE)
+            .cloned()
+            .unwrap_or_else(|| Cookie::named(SESSION_COOKIE))
+    }
+}
@@ -1,7 +1,13 @@
 use core::fmt::Display;
+use core::str::FromStr;
+use core::time::Duration;
 use std::env::var;
+use std::path::PathBuf;
 
+use crate::api::{GoogleUrls, HttpConfig, RetryPolicy};
 use crate::google::auth::credentials::GoogleAuthCredentials;
+use crate::google::drive::interface::NoteFormat;
+use crate::storage::StorageConfig;
 
 const ERR_PREFIX: &str = "Failed to fetch Google OAuth2 credentials: ";
 
@@ -11,6 +17,19 @@ pub struct Env {
     pub credentials: GoogleAuthCredentials,
     pub addr: (String, u16),
     pub app_folder: String,
+    /// Storage of the new notes: Google Docs, or plain Markdown files.
+    pub note_format: NoteFormat,
+    /// Base URLs of the Google services, only changed to test against a fake
+    /// Google.
+    pub google_urls: GoogleUrls,
+    pub storage: StorageConfig,
+    pub http: HttpConfig,
+    /// Directory of the local full-text index of the notes, searched instead
+    /// of Drive if set.
+    pub search_index: Option<PathBuf>,
+    /// Interval of the background sync of the listings with Drive, which are
+    /// then served from memory. Disabled if missing.
+    pub sync_interval: Option<Duration>,
 }
 
 pub fn unwrap_or_default<T, E, D, I>(res: Result<T, E>, default: I, var: D) -> T
@@ -25,12 +44,20 @@ pub fn load_env() -> Result<Env, String> {
     dotenv::from_filename(ENV_PATH)
         .map_err(|_err| format!("{ERR_PREFIX}Missing `{ENV_PATH}` file."))?;
 
+    let storage = load_storage()?;
+    // Local notes don't need a Google account, so neither do the credentials.
+    let credentials = if matches!(storage, StorageConfig::Drive) {
+        GoogleAuthCredentials::new(get_var("ID")?, get_var("REDIRECT_URI")?, get_var("SECRET")?)
+    } else {
+        GoogleAuthCredentials::new(
+            var("ID").unwrap_or_default(),
+            var("REDIRECT_URI").unwrap_or_default(),
+            var("SECRET").unwrap_or_default(),
+        )
+    };
+
     Ok(Env {
-        credentials: GoogleAuthCredentials::new(
-            get_var("ID")?,
-            get_var("REDIRECT_URI")?,
-            get_var("SECRET")?,
-        ),
+        credentials,
         addr: (
             unwrap_or_default(get_var("HOST"), "127.0.0.1", "HOST"),
             unwrap_or_default(
@@ -45,6 +72,87 @@ pub fn load_env() -> Result<Env, String> {
             ),
         ),
         app_folder: unwrap_or_default(get_var("APP_FOLDER"), "_@!md-viewer!@_", "APP_FOLDER"),
+        note_format: get_var("NOTE_FORMAT").map_or_else(
+            |_err| NoteFormat::default(),
+            |format| {
+                format.parse().unwrap_or_else(|err| {
+                    eprintln!("\n{err} Falling back to default.\n");
+                    NoteFormat::default()
+                })
+            },
+        ),
+        google_urls: load_google_urls(),
+        storage,
+        http: load_http(),
+        search_index: var("SEARCH_INDEX_DIR").ok().map(PathBuf::from),
+        sync_interval: load_sync_interval(),
+    })
+}
+
+/// Reads the storage of the notes: `drive` (default) or `local`, the latter
+/// reading the directory from `NOTES_DIR`.
+fn load_storage() -> Result<StorageConfig, String> {
+    match var("STORAGE").as_deref() {
+        Err(_) | Ok("drive") => Ok(StorageConfig::Drive),
+        Ok("local") => var("NOTES_DIR")
+            .map(|dir| StorageConfig::Local(PathBuf::from(dir)))
+            .map_err(|_err| {
+                format!("`STORAGE` is `local`, but `NOTES_DIR` is missing in `{ENV_PATH}`.")
+            }),
+        Ok(other) => Err(format!("Unknown storage `{other}`, expected `drive` or `local`.")),
+    }
+}
+
+/// Reads the optional `GOOGLE_*_URL` overrides of the Google base URLs.
+fn load_google_urls() -> GoogleUrls {
+    let default = GoogleUrls::default();
+    GoogleUrls {
+        accounts: var("GOOGLE_ACCOUNTS_URL").unwrap_or(default.accounts),
+        apis: var("GOOGLE_APIS_URL").unwrap_or(default.apis),
+        docs: var("GOOGLE_DOCS_URL").unwrap_or(default.docs),
+        oauth: var("GOOGLE_OAUTH_URL").unwrap_or(default.oauth),
+    }
+}
+
+/// Reads the optional `HTTP_*` and `RETRY_*` settings of the client calling
+/// Google, the durations being in seconds unless suffixed with `_MS`.
+fn load_http() -> HttpConfig {
+    let default = HttpConfig::default();
+    HttpConfig {
+        connect_timeout: parse_var("HTTP_CONNECT_TIMEOUT")
+            .map_or(default.connect_timeout, Duration::from_secs),
+        pool_max_idle_per_host: parse_var("HTTP_POOL_MAX_IDLE_PER_HOST")
+            .unwrap_or(default.pool_max_idle_per_host),
+        pool_idle_timeout: parse_var("HTTP_POOL_IDLE_TIMEOUT")
+            .map_or(default.pool_idle_timeout, Duration::from_secs),
+        timeout: parse_var("HTTP_TIMEOUT").map_or(default.timeout, Duration::from_secs),
+        user_agent: var("HTTP_USER_AGENT").unwrap_or(default.user_agent),
+        retry: RetryPolicy {
+            max_attempts: parse_var("RETRY_MAX_ATTEMPTS")
+                .filter(|attempts| *attempts > 0)
+                .unwrap_or(default.retry.max_attempts),
+            base_delay: parse_var("RETRY_BASE_DELAY_MS")
+                .map_or(default.retry.base_delay, Duration::from_millis),
+            max_delay: parse_var("RETRY_MAX_DELAY")
+                .map_or(default.retry.max_delay, Duration::from_secs),
+        },
+    }
+}
+
+/// Reads `SYNC_INTERVAL` in seconds, the sync being disabled unless it is
+/// set to more than 0.
+fn load_sync_interval() -> Option<Duration> {
+    parse_var("SYNC_INTERVAL")
+        .map(Duration::from_secs)
+        .filter(|interval| !interval.is_zero())
+}
+
+/// Parses an optional variable, warning if it is set but invalid.
+fn parse_var<T: FromStr>(env_var: &str) -> Option<T> {
+    let value = var(env_var).ok()?;
+    value.parse().ok().or_else(|| {
+        eprintln!("\n`{env_var}` is not valid: `{value}`. Falling back to default.\n");
+        None
     })
 }
 
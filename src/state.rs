@@ -1,40 +1,72 @@
-use core::result;
+use alloc::sync::Arc;
+use core::time::Duration;
 use std::sync::{Mutex, MutexGuard};
 
-use actix_web::{HttpRequest, HttpResponse, web};
+use actix_web::cookie::Cookie;
+use actix_web::{HttpRequest, HttpResponse, ResponseError as _, web};
 
+use crate::api::GoogleApi;
+use crate::error::{Error, Result};
 use crate::google::auth::credentials::GoogleAuthCredentials;
-use crate::google::auth::login::ClientOAuthData;
+use crate::google::auth::login::{ClientOAuthData, PendingLogin, refresh_access_token};
+use crate::google::drive::index::SearchIndex;
+use crate::google::drive::interface::NoteFormat;
 use crate::google::drive::manager::DriveManager;
+use crate::log;
+use crate::session::{Session, SessionStore};
+use crate::settings::Env;
+use crate::storage::{DriveStorage, LocalStorage, Storage, StorageConfig};
 
 pub type AppData = web::Data<AppState>;
 
-type Result<T, E = String> = result::Result<T, E>;
-
 #[derive(Debug)]
 pub struct AppState {
+    app_folder: String,
     app_name: &'static str,
-    callback: Mutex<Option<String>>,
-    client_oauth_data: Mutex<Option<ClientOAuthData>>,
     credentials: GoogleAuthCredentials,
-    drive: DriveManager,
+    google: GoogleApi,
+    /// Set when the notes are stored in a local directory instead of Google
+    /// Drive.
+    local_storage: Option<LocalStorage>,
+    note_format: NoteFormat,
+    /// Set when the notes are searched in a local index instead of Drive.
+    search_index: Option<SearchIndex>,
+    sessions: SessionStore,
+    /// Interval of the background sync of the listings, if enabled.
+    sync_interval: Option<Duration>,
+}
+
+/// Formats a revision as the value of an `ETag` header.
+pub fn to_etag(revision: &str) -> String {
+    format!("\"{revision}\"")
 }
 
-pub fn ok_or_internal(value: Result<String>) -> HttpResponse {
+pub fn ok_or_error(value: Result<String>) -> HttpResponse {
     match value {
         Ok(val) => HttpResponse::Ok().body(val),
-        Err(err) => HttpResponse::InternalServerError().body(err),
+        Err(err) => err.error_response(),
     }
 }
 
-pub fn map_err_internal<T>(value: Result<T>) -> Result<T, HttpResponse> {
-    value.map_err(|err| HttpResponse::InternalServerError().body(err))
+pub fn map_err_response<T>(value: Result<T>) -> Result<T, HttpResponse> {
+    value.map_err(|err| err.error_response())
+}
+
+#[macro_export]
+macro_rules! with_token {
+    ($data:ident, $req:ident, |$token:ident| $call:expr) => {
+        $crate::unwrap_return!($data.with_token(&$req, async |$token: &str| $call).await)
+    };
 }
 
 #[macro_export]
-macro_rules! token {
-    ($data:ident, $req:ident) => {
-        &$crate::unwrap_return!($data.to_token(&$req))
+macro_rules! with_storage {
+    ($data:ident, $req:ident, |$storage:ident| $call:expr) => {
+        $crate::unwrap_return!(
+            $data
+                .with_storage(&$req, async |$storage: &$crate::storage::Storage<'_>| $call)
+                .await
+        )
     };
 }
 
@@ -49,38 +81,162 @@ macro_rules! unwrap_return {
 }
 
 #[macro_export]
-macro_rules! unwrap_return_internal {
+macro_rules! unwrap_return_error {
     ($value:expr) => {
         match $value {
             Ok(val) => val,
-            Err(err) => return actix_web::HttpResponse::InternalServerError().body(err),
+            Err(err) => return actix_web::ResponseError::error_response(&err),
         }
     };
 }
 
 impl AppState {
-    pub fn new(credentials: GoogleAuthCredentials, app_folder: String) -> web::Data<Self> {
-        web::Data::new(Self {
+    pub fn new(settings: Env) -> Result<web::Data<Self>> {
+        Ok(web::Data::new(Self {
+            app_folder: settings.app_folder,
             app_name: "mdViewer",
-            credentials,
-            client_oauth_data: Mutex::default(),
-            callback: Mutex::default(),
-            drive: DriveManager::new(app_folder),
+            credentials: settings.credentials,
+            google: GoogleApi::new(&settings.http, settings.google_urls)?,
+            local_storage: match settings.storage {
+                StorageConfig::Drive => None,
+                StorageConfig::Local(root) => Some(LocalStorage::new(root)),
+            },
+            note_format: settings.note_format,
+            search_index: settings.search_index.map(SearchIndex::new),
+            sessions: SessionStore::new(),
+            sync_interval: settings.sync_interval,
+        }))
+    }
+
+    /// Returns the access token of the browser that sent the request,
+    /// refreshing it if it is about to expire.
+    ///
+    /// If that browser isn't logged in, the requested path is saved in its
+    /// session and the browser is redirected to the login page.
+    pub async fn to_token(&self, req: &HttpRequest) -> Result<Box<str>, HttpResponse> {
+        let Some(id) = map_err_response(self.sessions.to_id(req))? else {
+            return Err(self.redirect_to_login(req)?);
+        };
+        match map_err_response(self.sessions.with_session(&id, |session| {
+            session
+                .as_client_data()
+                .map(|client_data| (client_data.as_token().into(), client_data.is_expiring()))
+        }))? {
+            Some((token, false)) => Ok(token),
+            Some((_, true)) => self.refresh_token(req, &id).await,
+            None => Err(self.redirect_to_login(req)?),
+        }
+    }
+
+    /// Exchanges the session's refresh token for a new access token.
+    ///
+    /// If this isn't possible, the browser is redirected to the login page.
+    async fn refresh_token(&self, req: &HttpRequest, id: &str) -> Result<Box<str>, HttpResponse> {
+        let Some(refresh_token) = map_err_response(self.sessions.with_session(id, |session| {
+            session
+                .as_client_data()?
+                .as_refresh_token()
+                .map(str::to_owned)
+        }))?
+        else {
+            return Err(self.redirect_to_login(req)?);
+        };
+        log!("Refreshing access token");
+        match refresh_access_token(&self.google, &self.credentials, &refresh_token).await {
+            Ok(refreshed) => map_err_response(
+                self.sessions
+                    .with_session(id, |session| session.refresh_client_data(refreshed)),
+            )?
+            .map_or_else(|| Err(self.redirect_to_login(req)?), Ok),
+            Err(err) => {
+                log!("Failed to refresh access token:\n{err}");
+                Err(self.redirect_to_login(req)?)
+            }
+        }
+    }
+
+    /// Runs a Google API call with the access token of the browser that sent
+    /// the request.
+    ///
+    /// If Google rejects the token, it is refreshed and the call is retried
+    /// once.
+    pub async fn with_token<T, F>(
+        &self,
+        req: &HttpRequest,
+        call: F,
+    ) -> Result<Result<T>, HttpResponse>
+    where
+        F: AsyncFn(&str) -> Result<T>,
+    {
+        let token = self.to_token(req).await?;
+        match call(&token).await {
+            Err(err) if err.is_unauthorised() => {
+                let Some(id) = map_err_response(self.sessions.to_id(req))? else {
+                    return Err(self.redirect_to_login(req)?);
+                };
+                Ok(call(&self.refresh_token(req, &id).await?).await)
+            }
+            result => Ok(result),
+        }
+    }
+
+    /// Redirects the browser to the login page if the notes are stored in
+    /// Google Drive and it isn't logged in.
+    pub async fn ensure_logged_in(&self, req: &HttpRequest) -> Result<(), HttpResponse> {
+        if self.local_storage.is_none() {
+            self.to_token(req).await?;
+        }
+        Ok(())
+    }
+
+    /// Runs operations on the notes, with the storage of the browser that
+    /// sent the request.
+    ///
+    /// With Google Drive, the browser is redirected to the login page if it
+    /// isn't logged in, and the operations are retried once if Google rejects
+    /// the token.
+    pub async fn with_storage<T, F>(
+        &self,
+        req: &HttpRequest,
+        call: F,
+    ) -> Result<Result<T>, HttpResponse>
+    where
+        F: AsyncFn(&Storage<'_>) -> Result<T>,
+    {
+        if let Some(local) = &self.local_storage {
+            return Ok(call(&Storage::Local(local)).await);
+        }
+        let drive = self.to_drive(req)?;
+        self.with_token(req, async |token| {
+            if let Some(interval) = self.sync_interval {
+                drive.start_sync(&self.google, token, interval)?;
+            }
+            call(&Storage::Drive(DriveStorage::new(&drive, &self.google, token))).await
         })
+        .await
     }
 
-    pub fn to_token(&self, req: &HttpRequest) -> Result<Box<str>, HttpResponse> {
-        map_err_internal(unlock(&self.client_oauth_data, "client data"))?
-            .as_ref()
-            .map_or_else(
-                || {
-                    map_err_internal(self.set_callback(req.path().to_owned()))?;
-                    Err(HttpResponse::TemporaryRedirect()
-                        .append_header(("Location", "/auth/login"))
-                        .finish())
-                },
-                |client_data| Ok(client_data.as_token().to_owned().into_boxed_str()),
-            )
+    /// Returns the drive manager of the browser that sent the request.
+    pub fn to_drive(&self, req: &HttpRequest) -> Result<Arc<DriveManager>, HttpResponse> {
+        match map_err_response(self.sessions.to_id(req))? {
+            Some(id) => map_err_response(
+                self.sessions
+                    .with_session(&id, |session| session.to_drive()),
+            ),
+            None => Err(self.redirect_to_login(req)?),
+        }
+    }
+
+    fn redirect_to_login(&self, req: &HttpRequest) -> Result<HttpResponse, HttpResponse> {
+        let id = map_err_response(self.sessions.to_or_create_id(req, &self.app_folder))?;
+        map_err_response(
+            self.sessions
+                .with_session(&id, |session| session.set_callback(req.path().to_owned())),
+        )?;
+        Ok(HttpResponse::TemporaryRedirect()
+            .cookie(self.sessions.to_cookie(&id))
+            .append_header(("Location", "/auth/login"))
+            .finish())
     }
 
     pub const fn as_app_name(&self) -> &str {
@@ -91,29 +247,78 @@ impl AppState {
         &self.credentials
     }
 
-    pub const fn as_drive(&self) -> &DriveManager {
-        &self.drive
+    pub const fn as_google(&self) -> &GoogleApi {
+        &self.google
+    }
+
+    pub const fn as_note_format(&self) -> NoteFormat {
+        self.note_format
+    }
+
+    pub const fn as_search_index(&self) -> Option<&SearchIndex> {
+        self.search_index.as_ref()
+    }
+
+    /// Saves the secrets of a new login in the request's session.
+    ///
+    /// Returns the cookie identifying the session.
+    pub fn start_login(
+        &self,
+        req: &HttpRequest,
+        pending_login: PendingLogin,
+    ) -> Result<Cookie<'static>> {
+        let id = self.sessions.to_or_create_id(req, &self.app_folder)?;
+        self.sessions
+            .with_session(&id, |session| session.set_pending_login(pending_login))?;
+        Ok(self.sessions.to_cookie(&id))
     }
 
-    fn set_callback(&self, new_callback: String) -> Result<()> {
-        unlock(&self.callback, "callback")
-            .map(|mut old_callback| *old_callback = Some(new_callback))
+    /// Removes the secrets of the login in progress from the request's session.
+    pub fn take_pending_login(&self, req: &HttpRequest) -> Result<Option<PendingLogin>> {
+        self.sessions
+            .to_id(req)?
+            .map_or(Ok(None), |id| self.sessions.with_session(&id, Session::take_pending_login))
     }
 
-    pub fn take_callback(&self) -> Result<String> {
-        unlock(&self.callback, "callback")
-            .map(|mut callback| callback.take().unwrap_or_else(|| "/auth/info".to_owned()))
+    /// Stores the client data in the request's session, under a new session
+    /// id.
+    ///
+    /// Returns the path to redirect to, and the cookie identifying the new
+    /// session.
+    pub fn log_in(
+        &self,
+        req: &HttpRequest,
+        new_client_data: ClientOAuthData,
+    ) -> Result<(String, Cookie<'static>)> {
+        let old_id = self.sessions.to_or_create_id(req, &self.app_folder)?;
+        let id = self.sessions.renew(&old_id, &self.app_folder)?;
+        let callback = self.sessions.with_session(&id, |session| {
+            session.set_client_data(new_client_data);
+            session
+                .take_callback()
+                .unwrap_or_else(|| "/auth/info".to_owned())
+        })?;
+        Ok((callback, self.sessions.to_cookie(&id)))
     }
 
-    pub fn set_client_data(&self, new_client_data: ClientOAuthData) -> Result<(), HttpResponse> {
-        unlock(&self.client_oauth_data, "callback")
-            .map_err(|err| HttpResponse::Ok().body(err))
-            .map(|mut old_client_data| *old_client_data = Some(new_client_data))
+    /// Deletes the request's session.
+    ///
+    /// Returns the client data the session held, and the cookie removing the
+    /// session from the browser.
+    pub fn log_out(&self, req: &HttpRequest) -> Result<(Option<ClientOAuthData>, Cookie<'static>)> {
+        let client_data = match self.sessions.to_id(req)? {
+            Some(id) => self
+                .sessions
+                .remove(&id)?
+                .and_then(|mut session| session.take_client_data()),
+            None => None,
+        };
+        Ok((client_data, SessionStore::to_removal_cookie()))
     }
 }
 
-fn lock_error_msg(data_type: &str, err: &impl ToString) -> String {
-    format!("Failed to obtain lock for {data_type}:\n{}", err.to_string())
+fn lock_error_msg(data_type: &str, err: &impl ToString) -> Error {
+    Error::Internal(format!("Failed to obtain lock for {data_type}:\n{}", err.to_string()))
 }
 
 pub fn unlock<'data, T>(
@@ -0,0 +1,181 @@
+use super::{Note, NoteList, NoteMetadata, NoteStorage};
+use crate::api::GoogleApi;
+use crate::error::{Error, Result};
+use crate::google::drive::action::{create_file_with_name, get_note, set_file_content};
+use crate::google::drive::interface::{
+    NoteFormat, PageQuery, create_folder, create_markdown_file, delete_file, folder_contents, folder_trash, get_file_metadata, get_file_parents, move_file, rename_file, set_trashed
+};
+use crate::google::drive::manager::DriveManager;
+use crate::google::drive::sync::MetadataCache;
+
+/// App folder in the Google Drive of the user that sent the request.
+pub struct DriveStorage<'req> {
+    drive: &'req DriveManager,
+    google: &'req GoogleApi,
+    token: &'req str,
+}
+
+impl<'req> DriveStorage<'req> {
+    pub const fn new(drive: &'req DriveManager, google: &'req GoogleApi, token: &'req str) -> Self {
+        Self { drive, google, token }
+    }
+
+    pub const fn as_google(&self) -> &GoogleApi {
+        self.google
+    }
+
+    pub const fn as_token(&self) -> &str {
+        self.token
+    }
+
+    pub async fn app_folder_id(&self) -> Result<Box<str>> {
+        self.drive.app_folder_id(self.google, self.token).await
+    }
+
+    /// Returns the cache of the listings, synced if it fell behind, or `None`
+    /// if the sync is disabled.
+    pub async fn to_synced_cache(&self) -> Result<Option<&MetadataCache>> {
+        let Some(cache) = self.drive.as_cache() else {
+            return Ok(None);
+        };
+        cache
+            .ensure_synced(self.google, self.token, &self.app_folder_id().await?)
+            .await?;
+        Ok(Some(cache))
+    }
+
+    /// Makes the next listing sync first if the files were changed.
+    fn mark_changed<T>(&self, result: Result<T>) -> Result<T> {
+        if result.is_ok()
+            && let Some(cache) = self.drive.as_cache()
+        {
+            cache.mark_stale()?;
+        }
+        result
+    }
+
+    async fn ensure_in_app_folder(&self, id: &str) -> Result<()> {
+        self.drive
+            .ensure_in_app_folder(self.google, self.token, id)
+            .await
+    }
+
+    /// Returns the id of a folder inside the app folder, or of the app folder
+    /// itself if none is given.
+    pub async fn to_folder_id(&self, folder: Option<&str>) -> Result<Box<str>> {
+        let app_folder_id = self.app_folder_id().await?;
+        match folder {
+            Some(folder_id) if folder_id != &*app_folder_id => {
+                self.ensure_in_app_folder(folder_id).await?;
+                Ok(folder_id.into())
+            }
+            _ => Ok(app_folder_id),
+        }
+    }
+
+    /// Creates an empty note in a folder of the app folder, or in the app
+    /// folder itself if none is given.
+    pub async fn create_in(
+        &self,
+        name: &str,
+        format: NoteFormat,
+        folder: Option<&str>,
+    ) -> Result<NoteMetadata> {
+        let folder_id = self.to_folder_id(folder).await?;
+        let created = match format {
+            NoteFormat::Document =>
+                create_file_with_name(self.google, name, &folder_id, self.token).await,
+            NoteFormat::Markdown =>
+                create_markdown_file(self.google, self.token, name, &folder_id).await,
+        };
+        self.mark_changed(created.map(NoteMetadata::from))
+    }
+
+    /// Creates a subfolder, in the app folder if no parent is given.
+    pub async fn create_folder(&self, name: &str, parent: Option<&str>) -> Result<NoteMetadata> {
+        let parent_id = self.to_folder_id(parent).await?;
+        let created = create_folder(self.google, self.token, name, Some(&parent_id)).await;
+        self.mark_changed(created.map(NoteMetadata::from))
+    }
+
+    /// Moves a note or a subfolder to another folder, the app folder if none
+    /// is given.
+    pub async fn move_to(&self, id: &str, folder: Option<&str>) -> Result<NoteMetadata> {
+        self.ensure_in_app_folder(id).await?;
+        let folder_id = self.to_folder_id(folder).await?;
+        if folder.is_some()
+            && !get_file_metadata(self.google, self.token, &folder_id, Some("id,mimeType"))
+                .await?
+                .is_folder()
+        {
+            return Err(Error::BadRequest(format!("{folder_id} is not a folder.")));
+        }
+        let parents = get_file_parents(self.google, self.token, id).await?;
+        let moved = move_file(self.google, self.token, id, &parents, &folder_id).await;
+        self.mark_changed(moved.map(NoteMetadata::from))
+    }
+}
+
+impl NoteStorage for DriveStorage<'_> {
+    async fn list(&self, page: &PageQuery) -> Result<NoteList> {
+        let folder_id = self.to_folder_id(page.as_folder()).await?;
+        if let Some(cache) = self.to_synced_cache().await?
+            && let Some(list) = cache.list(&folder_id, page)?
+        {
+            return Ok(list);
+        }
+        folder_contents(self.google, self.token, &folder_id, page)
+            .await
+            .map(NoteList::from)
+    }
+
+    async fn create(&self, name: &str, format: NoteFormat) -> Result<NoteMetadata> {
+        self.create_in(name, format, None).await
+    }
+
+    async fn read(&self, id: &str) -> Result<Note> {
+        get_note(self.google, id, self.token).await
+    }
+
+    async fn write(&self, id: &str, content: &str, expected: Option<&str>) -> Result<String> {
+        let written = set_file_content(self.google, id, content, expected, self.token).await;
+        self.mark_changed(written)
+    }
+
+    async fn delete(&self, id: &str) -> Result<()> {
+        self.ensure_in_app_folder(id).await?;
+        let deleted = delete_file(self.google, self.token, id).await;
+        self.mark_changed(deleted)
+    }
+
+    async fn trash(&self, id: &str) -> Result<NoteMetadata> {
+        self.ensure_in_app_folder(id).await?;
+        let trashed = set_trashed(self.google, self.token, id, true).await;
+        self.mark_changed(trashed.map(NoteMetadata::from))
+    }
+
+    async fn restore(&self, id: &str) -> Result<NoteMetadata> {
+        self.ensure_in_app_folder(id).await?;
+        let restored = set_trashed(self.google, self.token, id, false).await;
+        self.mark_changed(restored.map(NoteMetadata::from))
+    }
+
+    async fn list_trash(&self, page: &PageQuery) -> Result<NoteList> {
+        let folder_id = self.to_folder_id(page.as_folder()).await?;
+        folder_trash(self.google, self.token, &folder_id, page)
+            .await
+            .map(NoteList::from)
+    }
+
+    async fn rename(&self, id: &str, name: &str) -> Result<NoteMetadata> {
+        self.ensure_in_app_folder(id).await?;
+        let renamed = rename_file(self.google, self.token, id, name).await;
+        self.mark_changed(renamed.map(NoteMetadata::from))
+    }
+
+    async fn metadata(&self, id: &str, fields: Option<&str>) -> Result<NoteMetadata> {
+        get_file_metadata(self.google, self.token, id, fields)
+            .await
+            .map(NoteMetadata::from)
+    }
+}
@@ -0,0 +1,292 @@
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+
+use actix_web::web;
+use base64::Engine as _;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use sha2::{Digest as _, Sha256};
+
+use super::{Note, NoteList, NoteMetadata, NoteStorage, to_markdown_name};
+use crate::error::{Error, Result};
+use crate::google::drive::interface::{MARKDOWN_MIME_TYPE, NoteFormat, PageQuery};
+
+/// Directory of Markdown files on the server, the name of each file being its
+/// id.
+#[derive(Debug)]
+pub struct LocalStorage {
+    root: PathBuf,
+    /// Held while a note is checked and written, so that concurrent saves
+    /// can't both pass the revision check.
+    write_lock: async_lock::Mutex<()>,
+}
+
+/// Directory of the trashed notes, hidden from the listings as its name starts
+/// with a dot.
+const TRASH_DIR: &str = ".trash";
+
+/// Revision of a local note: the hash of its content.
+fn to_revision(content: &str) -> String {
+    URL_SAFE_NO_PAD.encode(Sha256::digest(content.as_bytes()))
+}
+
+fn to_error(err: &io::Error, id: &str) -> Error {
+    if err.kind() == io::ErrorKind::NotFound {
+        Error::NotFound(format!("Note not found: {id}."))
+    } else {
+        Error::Internal(format!("Failed to access note {id}: {err}"))
+    }
+}
+
+/// Runs file operations on the blocking thread pool, so that they don't hold
+/// up the workers.
+async fn run_blocking<T: Send + 'static>(
+    call: impl FnOnce() -> Result<T> + Send + 'static,
+) -> Result<T> {
+    web::block(call)
+        .await
+        .map_err(|err| Error::Internal(format!("Failed to access the notes: {err}")))?
+}
+
+fn to_metadata(id: &str) -> NoteMetadata {
+    NoteMetadata {
+        id: id.to_owned(),
+        kind: "md-viewer#file".to_owned(),
+        mime_type: MARKDOWN_MIME_TYPE.to_owned(),
+        name: id.to_owned(),
+        ..NoteMetadata::default()
+    }
+}
+
+fn is_note(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("md"))
+}
+
+/// Returns the sorted names of the notes of a directory.
+fn note_names(dir: &Path) -> Result<Vec<String>> {
+    let mut names = fs::read_dir(dir)
+        .map_err(|err| Error::Internal(format!("Failed to list the notes: {err}")))?
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let name = path.file_name()?.to_str()?;
+            (path.is_file() && is_note(&path) && !name.starts_with('.')).then(|| name.to_owned())
+        })
+        .collect::<Vec<_>>();
+    names.sort_unstable();
+    Ok(names)
+}
+
+/// Keeps the notes matching the filters of the page, in its order.
+///
+/// Local notes are never starred nor Google files, and their names are their
+/// only sort key.
+fn filter_notes(names: &[String], page: &PageQuery) -> Result<Vec<String>> {
+    let order = page.to_order()?;
+    if order.as_ref().is_some_and(|sort| !sort.is_by_name()) {
+        return Err(Error::BadRequest("Local notes can only be ordered by name.".to_owned()));
+    }
+    if page.is_starred_only() || page.to_file_type()?.is_some() {
+        return Ok(vec![]);
+    }
+    let mut filtered: Vec<String> = names
+        .iter()
+        .filter(|name| page.is_name_matching(name))
+        .cloned()
+        .collect();
+    if order.is_some_and(|sort| sort.is_descending()) {
+        filtered.reverse();
+    }
+    Ok(filtered)
+}
+
+/// Returns the requested page of the notes, the page token being the index
+/// of its first note.
+fn to_page(all_names: &[String], page: &PageQuery) -> Result<NoteList> {
+    if page.as_folder().is_some() {
+        return Err(Error::BadRequest(
+            "Folders need the notes to be stored in Google Drive.".to_owned(),
+        ));
+    }
+    let names = filter_notes(all_names, page)?;
+    let start: usize = page
+        .as_page_token()
+        .map_or(Ok(0), str::parse)
+        .map_err(|_err| Error::BadRequest("Invalid page token.".to_owned()))?;
+    let end = page
+        .as_page_size()
+        .map_or(names.len(), |size| start.saturating_add(size.into()).min(names.len()));
+    Ok(NoteList {
+        files: names
+            .get(start..end)
+            .unwrap_or_default()
+            .iter()
+            .map(|name| to_metadata(name))
+            .collect(),
+        incomplete_search: false,
+        kind: "md-viewer#fileList".to_owned(),
+        next_page_token: (end < names.len()).then(|| end.to_string()),
+    })
+}
+
+impl LocalStorage {
+    pub const fn new(root: PathBuf) -> Self {
+        Self { root, write_lock: async_lock::Mutex::new(()) }
+    }
+
+    /// Returns the path of a note, rejecting the ids that could escape the
+    /// directory or reach hidden files.
+    fn to_path(&self, id: &str) -> Result<PathBuf> {
+        if id.starts_with('.') || id.contains(['/', '\\']) || !is_note(Path::new(id)) {
+            Err(Error::BadRequest(format!("Invalid note id {id}.")))
+        } else {
+            Ok(self.root.join(id))
+        }
+    }
+
+    fn to_trash_path(&self, id: &str) -> Result<PathBuf> {
+        self.to_path(id)?;
+        Ok(self.root.join(TRASH_DIR).join(id))
+    }
+
+    /// Moves a note between the directory and the trash, refusing to
+    /// overwrite a note with the same name.
+    async fn move_note(&self, id: &str, from: PathBuf, to: PathBuf) -> Result<NoteMetadata> {
+        let _guard = self.write_lock.lock().await;
+        let moved_id = id.to_owned();
+        run_blocking(move || {
+            if to.exists() {
+                return Err(Error::BadRequest(format!(
+                    "A note named {moved_id} is already there."
+                )));
+            }
+            fs::rename(from, to).map_err(|err| to_error(&err, &moved_id))
+        })
+        .await?;
+        Ok(to_metadata(id))
+    }
+
+    async fn read_content(&self, id: &str) -> Result<String> {
+        let path = self.to_path(id)?;
+        let read_id = id.to_owned();
+        run_blocking(move || fs::read_to_string(path).map_err(|err| to_error(&err, &read_id))).await
+    }
+}
+
+impl NoteStorage for LocalStorage {
+    async fn list(&self, page: &PageQuery) -> Result<NoteList> {
+        let root = self.root.clone();
+        to_page(&run_blocking(move || note_names(&root)).await?, page)
+    }
+
+    /// Creates an empty Markdown file, whatever the format, as the directory
+    /// can only hold plain files.
+    async fn create(&self, name: &str, _format: NoteFormat) -> Result<NoteMetadata> {
+        let id = to_markdown_name(name);
+        let path = self.to_path(&id)?;
+        let created_id = id.clone();
+        run_blocking(move || match File::create_new(path) {
+            Ok(_) => Ok(()),
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists =>
+                Err(Error::BadRequest(format!("A note named {created_id} already exists."))),
+            Err(err) => Err(to_error(&err, &created_id)),
+        })
+        .await?;
+        Ok(to_metadata(&id))
+    }
+
+    async fn read(&self, id: &str) -> Result<Note> {
+        let content = self.read_content(id).await?;
+        Ok(Note { revision: to_revision(&content), content })
+    }
+
+    async fn write(&self, id: &str, content: &str, expected: Option<&str>) -> Result<String> {
+        let _guard = self.write_lock.lock().await;
+        let current = self.read_content(id).await?;
+        let revision = to_revision(&current);
+        if expected.is_some_and(|expected_revision| expected_revision != revision) {
+            return Err(Error::Conflict { content: current, revision });
+        }
+        // Written next to the note then moved over it, so that a failure
+        // can't leave it half written.
+        let path = self.to_path(id)?;
+        let temporary = self.root.join(format!(".{id}.tmp"));
+        let (written_id, new_content) = (id.to_owned(), content.to_owned());
+        run_blocking(move || {
+            fs::write(&temporary, new_content)
+                .and_then(|()| fs::rename(&temporary, &path))
+                .map_err(|err| to_error(&err, &written_id))
+        })
+        .await?;
+        Ok(to_revision(content))
+    }
+
+    /// Deletes the note, or its trashed copy if there is no note with this
+    /// name.
+    async fn delete(&self, id: &str) -> Result<()> {
+        let (path, trash_path) = (self.to_path(id)?, self.to_trash_path(id)?);
+        let deleted_id = id.to_owned();
+        run_blocking(move || match fs::remove_file(path) {
+            Err(err) if err.kind() == io::ErrorKind::NotFound =>
+                fs::remove_file(trash_path).map_err(|trash_err| to_error(&trash_err, &deleted_id)),
+            result => result.map_err(|err| to_error(&err, &deleted_id)),
+        })
+        .await
+    }
+
+    async fn trash(&self, id: &str) -> Result<NoteMetadata> {
+        let trash_path = self.to_trash_path(id)?;
+        let trash = self.root.join(TRASH_DIR);
+        run_blocking(move || {
+            fs::create_dir_all(trash)
+                .map_err(|err| Error::Internal(format!("Failed to create the trash: {err}")))
+        })
+        .await?;
+        self.move_note(id, self.to_path(id)?, trash_path).await
+    }
+
+    async fn restore(&self, id: &str) -> Result<NoteMetadata> {
+        self.move_note(id, self.to_trash_path(id)?, self.to_path(id)?)
+            .await
+    }
+
+    async fn list_trash(&self, page: &PageQuery) -> Result<NoteList> {
+        let trash = self.root.join(TRASH_DIR);
+        let names = run_blocking(move || {
+            if trash.is_dir() {
+                note_names(&trash)
+            } else {
+                Ok(vec![])
+            }
+        })
+        .await?;
+        to_page(&names, page)
+    }
+
+    async fn rename(&self, id: &str, name: &str) -> Result<NoteMetadata> {
+        let new_id = to_markdown_name(name);
+        let new_path = self.to_path(&new_id)?;
+        let path = self.to_path(id)?;
+        let (renamed_id, taken_id) = (id.to_owned(), new_id.clone());
+        let _guard = self.write_lock.lock().await;
+        run_blocking(move || {
+            if new_path.exists() {
+                return Err(Error::BadRequest(format!("A note named {taken_id} already exists.")));
+            }
+            fs::rename(path, new_path).map_err(|err| to_error(&err, &renamed_id))
+        })
+        .await?;
+        Ok(to_metadata(&new_id))
+    }
+
+    /// Returns the metadata of a note with its size, the fields being
+    /// ignored.
+    async fn metadata(&self, id: &str, _fields: Option<&str>) -> Result<NoteMetadata> {
+        let path = self.to_path(id)?;
+        let read_id = id.to_owned();
+        let size = run_blocking(move || fs::metadata(path).map_err(|err| to_error(&err, &read_id)))
+            .await?
+            .len();
+        Ok(NoteMetadata { size: Some(size.to_string()), ..to_metadata(id) })
+    }
+}
@@ -0,0 +1,221 @@
+mod drive;
+mod local;
+
+use std::path::PathBuf;
+
+pub use drive::DriveStorage;
+pub use local::LocalStorage;
+use serde::{Deserialize, Serialize};
+
+use crate::error::{Error, Result};
+use crate::google::drive::interface::{NoteFormat, PageQuery};
+
+/// Backend storing the notes, as selected in the settings.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum StorageConfig {
+    /// App folder in the Google Drive of each logged in user.
+    Drive,
+    /// Directory of Markdown files on the server, shared without login.
+    Local(PathBuf),
+}
+
+/// Content of a note, with the revision it was read at.
+pub struct Note {
+    pub content: String,
+    pub revision: String,
+}
+
+/// Account owning or editing a note.
+#[derive(Deserialize, Serialize, Debug, Default, Clone)]
+#[serde(default, rename_all = "camelCase")]
+pub struct NoteUser {
+    pub display_name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub email_address: Option<String>,
+}
+
+/// The optional fields are only set if the storage keeps them and the client
+/// selected them.
+#[derive(Serialize, Debug, Default, Clone)]
+#[serde(rename_all = "camelCase")]
+pub struct NoteMetadata {
+    pub id: String,
+    pub kind: String,
+    pub mime_type: String,
+    pub name: String,
+    /// RFC 3339 date-time.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created_time: Option<String>,
+    /// RFC 3339 date-time.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub modified_time: Option<String>,
+    /// Size in bytes, as a string like Drive's 64-bit integers, missing for
+    /// Google Docs and folders.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub size: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub parents: Vec<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub owners: Vec<NoteUser>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_modifying_user: Option<NoteUser>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub starred: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub trashed: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub web_view_link: Option<String>,
+    /// Version of the note, increasing with every change of its content or
+    /// metadata.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+/// Page of the notes of the storage.
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+pub struct NoteList {
+    pub files: Vec<NoteMetadata>,
+    pub incomplete_search: bool,
+    pub kind: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_page_token: Option<String>,
+}
+
+/// Operations on the notes, independent of where they are stored.
+pub trait NoteStorage {
+    async fn list(&self, page: &PageQuery) -> Result<NoteList>;
+
+    /// Creates an empty note, in the given format if the storage supports
+    /// several.
+    async fn create(&self, name: &str, format: NoteFormat) -> Result<NoteMetadata>;
+
+    async fn read(&self, id: &str) -> Result<Note>;
+
+    /// Replaces the content of a note and returns its new revision.
+    ///
+    /// If an expected revision is given and the note moved on since, it is
+    /// left untouched and [`Error::Conflict`] is returned with its current
+    /// content.
+    async fn write(&self, id: &str, content: &str, expected: Option<&str>) -> Result<String>;
+
+    /// Deletes a note permanently, skipping the trash.
+    async fn delete(&self, id: &str) -> Result<()>;
+
+    /// Moves a note to the trash, from where it can be restored.
+    async fn trash(&self, id: &str) -> Result<NoteMetadata>;
+
+    async fn restore(&self, id: &str) -> Result<NoteMetadata>;
+
+    async fn list_trash(&self, page: &PageQuery) -> Result<NoteList>;
+
+    async fn rename(&self, id: &str, name: &str) -> Result<NoteMetadata>;
+
+    /// Returns the metadata of a note, with the given fields in Drive's
+    /// syntax if the storage supports selecting them.
+    async fn metadata(&self, id: &str, fields: Option<&str>) -> Result<NoteMetadata>;
+}
+
+/// Storage of the notes for one request.
+pub enum Storage<'req> {
+    Drive(DriveStorage<'req>),
+    Local(&'req LocalStorage),
+}
+
+impl Storage<'_> {
+    /// Returns the Drive storage, for the operations that only exist in
+    /// Google Drive.
+    pub fn as_drive(&self) -> Result<&DriveStorage<'_>> {
+        match self {
+            Self::Drive(drive) => Ok(drive),
+            Self::Local(_) => Err(Error::BadRequest(
+                "This operation needs the notes to be stored in Google Drive.".to_owned(),
+            )),
+        }
+    }
+}
+
+impl NoteStorage for Storage<'_> {
+    async fn list(&self, page: &PageQuery) -> Result<NoteList> {
+        match self {
+            Self::Drive(drive) => drive.list(page).await,
+            Self::Local(local) => local.list(page).await,
+        }
+    }
+
+    async fn create(&self, name: &str, format: NoteFormat) -> Result<NoteMetadata> {
+        match self {
+            Self::Drive(drive) => drive.create(name, format).await,
+            Self::Local(local) => local.create(name, format).await,
+        }
+    }
+
+    async fn read(&self, id: &str) -> Result<Note> {
+        match self {
+            Self::Drive(drive) => drive.read(id).await,
+            Self::Local(local) => local.read(id).await,
+        }
+    }
+
+    async fn write(&self, id: &str, content: &str, expected: Option<&str>) -> Result<String> {
+        match self {
+            Self::Drive(drive) => drive.write(id, content, expected).await,
+            Self::Local(local) => local.write(id, content, expected).await,
+        }
+    }
+
+    async fn delete(&self, id: &str) -> Result<()> {
+        match self {
+            Self::Drive(drive) => drive.delete(id).await,
+            Self::Local(local) => local.delete(id).await,
+        }
+    }
+
+    async fn trash(&self, id: &str) -> Result<NoteMetadata> {
+        match self {
+            Self::Drive(drive) => drive.trash(id).await,
+            Self::Local(local) => local.trash(id).await,
+        }
+    }
+
+    async fn restore(&self, id: &str) -> Result<NoteMetadata> {
+        match self {
+            Self::Drive(drive) => drive.restore(id).await,
+            Self::Local(local) => local.restore(id).await,
+        }
+    }
+
+    async fn list_trash(&self, page: &PageQuery) -> Result<NoteList> {
+        match self {
+            Self::Drive(drive) => drive.list_trash(page).await,
+            Self::Local(local) => local.list_trash(page).await,
+        }
+    }
+
+    async fn rename(&self, id: &str, name: &str) -> Result<NoteMetadata> {
+        match self {
+            Self::Drive(drive) => drive.rename(id, name).await,
+            Self::Local(local) => local.rename(id, name).await,
+        }
+    }
+
+    async fn metadata(&self, id: &str, fields: Option<&str>) -> Result<NoteMetadata> {
+        match self {
+            Self::Drive(drive) => drive.metadata(id, fields).await,
+            Self::Local(local) => local.metadata(id, fields).await,
+        }
+    }
+}
+
+/// Adds the `.md` extension to a note name if it is missing.
+pub fn to_markdown_name(name: &str) -> String {
+    if PathBuf::from(name)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("md"))
+    {
+        name.to_owned()
+    } else {
+        format!("{name}.md")
+    }
+}
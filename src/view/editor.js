@@ -0,0 +1,129 @@
+"use strict";
+
+const editor = document.querySelector(".editor");
+const id = encodeURIComponent(editor.dataset.id);
+const source = document.getElementById("source");
+const preview = document.getElementById("preview");
+const status = document.getElementById("status");
+const saveButton = document.getElementById("save");
+
+const PREVIEW_DELAY_MS = 300;
+
+let savedContent = "";
+let revision = null;
+let previewTimer = null;
+let saving = false;
+
+function setStatus(text, isError = false) {
+    status.textContent = text;
+    status.classList.toggle("error", isError);
+}
+
+function isDirty() {
+    return source.value !== savedContent;
+}
+
+function updateStatus() {
+    if (!saving) {
+        setStatus(isDirty() ? "Unsaved changes" : "All changes saved");
+    }
+}
+
+async function renderPreview() {
+    const response = await fetch("/view/preview", {
+        method: "POST",
+        headers: { "Content-Type": "text/plain; charset=utf-8" },
+        body: source.value,
+    });
+    if (response.ok) {
+        preview.innerHTML = await response.text();
+    }
+}
+
+function schedulePreview() {
+    clearTimeout(previewTimer);
+    previewTimer = setTimeout(renderPreview, PREVIEW_DELAY_MS);
+}
+
+async function load() {
+    const response = await fetch(`/drive/action/get-content/${id}`);
+    const text = await response.text();
+    if (!response.ok) {
+        setStatus(`Failed to load the note: ${text}`, true);
+        return;
+    }
+    revision = response.headers.get("ETag");
+    savedContent = text.replace(/^\uFEFF/, "").replace(/\r\n/g, "\n");
+    source.value = savedContent;
+    source.disabled = false;
+    saveButton.disabled = false;
+    updateStatus();
+    await renderPreview();
+}
+
+async function save() {
+    if (saving) {
+        return;
+    }
+    saving = true;
+    saveButton.disabled = true;
+    setStatus("Saving…");
+    const content = source.value;
+    try {
+        const headers = { "Content-Type": "text/plain; charset=utf-8" };
+        if (revision !== null) {
+            headers["If-Match"] = revision;
+        }
+        const response = await fetch(`/drive/action/set-content/${id}`, {
+            method: "POST",
+            headers,
+            body: content,
+        });
+        if (response.ok) {
+            revision = response.headers.get("ETag");
+            savedContent = content;
+            saving = false;
+            updateStatus();
+        } else if (response.status === 409) {
+            // Saving again overwrites the other version on purpose.
+            revision = response.headers.get("ETag");
+            saving = false;
+            setStatus(
+                "The note was changed elsewhere since it was loaded. " +
+                    "Save again to overwrite it, or reload the page to get the latest version.",
+                true,
+            );
+        } else {
+            saving = false;
+            setStatus(`Failed to save: ${await response.text()}`, true);
+        }
+    } catch (error) {
+        saving = false;
+        setStatus(`Failed to save: ${error}`, true);
+    } finally {
+        saveButton.disabled = false;
+    }
+}
+
+source.addEventListener("input", () => {
+    updateStatus();
+    schedulePreview();
+});
+
+saveButton.addEventListener("click", save);
+
+document.addEventListener("keydown", (event) => {
+    if ((event.ctrlKey || event.metaKey) && event.key === "s") {
+        event.preventDefault();
+        save();
+    }
+});
+
+window.addEventListener("beforeunload", (event) => {
+    if (isDirty()) {
+        event.preventDefault();
+        event.returnValue = "";
+    }
+});
+
+load();
@@ -0,0 +1,26 @@
+use super::render::{escape, html_page};
+
+const EDITOR_JS: &str = include_str!("editor.js");
+
+/// Builds the page of the split editor, that loads and saves the note from the
+/// browser.
+pub fn editor_page(id: &str) -> String {
+    let escaped_id = escape(id);
+    html_page(
+        &format!("Editing {id}"),
+        &format!(
+            "<div class=\"editor\" data-id=\"{escaped_id}\">\n\
+             <header class=\"editor-toolbar\">\n\
+             <a href=\"/view/{escaped_id}\">View</a>\n\
+             <span class=\"editor-status\" id=\"status\">Loading…</span>\n\
+             <button id=\"save\" type=\"button\" disabled>Save</button>\n\
+             </header>\n\
+             <div class=\"editor-panes\">\n\
+             <textarea id=\"source\" spellcheck=\"false\" disabled></textarea>\n\
+             <main id=\"preview\" class=\"markdown-body\"></main>\n\
+             </div>\n\
+             </div>\n\
+             <script>\n{EDITOR_JS}</script>"
+        ),
+    )
+}
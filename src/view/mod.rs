@@ -0,0 +1,76 @@
+mod editor;
+pub mod render;
+
+use actix_web::http::StatusCode;
+use actix_web::http::header::ContentType;
+use actix_web::{HttpRequest, HttpResponse, ResponseError as _, web};
+use editor::editor_page;
+use render::{escape, html_page, render_markdown};
+
+use crate::state::AppData;
+use crate::storage::NoteStorage as _;
+use crate::{unwrap_return, with_storage};
+
+#[actix_web::get("/view/{id}")]
+async fn view(data: AppData, req: HttpRequest, path: web::Path<(String,)>) -> HttpResponse {
+    let id = path.into_inner().0;
+    match with_storage!(data, req, |storage| storage.read(&id).await) {
+        Ok(note) => HttpResponse::Ok()
+            .content_type(ContentType::html())
+            .body(html_page(
+                &id,
+                &format!(
+                    "<main class=\"markdown-body\">\n{}</main>",
+                    render_markdown(&note.content)
+                ),
+            )),
+        Err(err) => message_page(
+            err.status_code(),
+            "Failed to load the note",
+            &err.to_string(),
+            ("/drive/ls", "Notes"),
+        ),
+    }
+}
+
+#[actix_web::post("/view/preview")]
+async fn preview(data: AppData, req: HttpRequest, content: String) -> HttpResponse {
+    unwrap_return!(data.ensure_logged_in(&req).await);
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(render_markdown(&content))
+}
+
+#[actix_web::get("/edit/{id}")]
+async fn edit(data: AppData, req: HttpRequest, path: web::Path<(String,)>) -> HttpResponse {
+    // Log in before serving the page, as its requests can't follow the
+    // redirections of the OAuth flow.
+    unwrap_return!(data.ensure_logged_in(&req).await);
+    HttpResponse::Ok()
+        .content_type(ContentType::html())
+        .body(editor_page(&path.into_inner().0))
+}
+
+/// Builds an HTML page with a message to the user, and a link to go on from
+/// there.
+pub fn message_page(
+    status: StatusCode,
+    title: &str,
+    message: &str,
+    (link, link_text): (&str, &str),
+) -> HttpResponse {
+    HttpResponse::build(status).content_type(ContentType::html()).body(html_page(
+        title,
+        &format!(
+            "<main class=\"markdown-body\">\n<h1>{}</h1>\n<p>{}</p>\n<p><a href=\"{}\">{}</a></p>\n</main>",
+            escape(title),
+            escape(message),
+            escape(link),
+            escape(link_text)
+        ),
+    ))
+}
+
+pub fn view_config(cfg: &mut web::ServiceConfig) {
+    cfg.service(preview).service(view).service(edit);
+}
@@ -0,0 +1,168 @@
+use core::mem;
+
+use pulldown_cmark::{CowStr, Event, LinkType, Options, Parser, Tag, TagEnd, html};
+use pulldown_cmark_escape::{FmtWriter, escape_html};
+
+const STYLE: &str = include_str!("style.css");
+
+const URL_PREFIXES: [&str; 3] = ["https://", "http://", "www."];
+
+/// Schemes of the links and images kept in notes, besides relative URLs.
+const SAFE_SCHEMES: [&str; 3] = ["http", "https", "mailto"];
+
+fn options() -> Options {
+    Options::ENABLE_TABLES
+        | Options::ENABLE_FOOTNOTES
+        | Options::ENABLE_STRIKETHROUGH
+        | Options::ENABLE_TASKLISTS
+        | Options::ENABLE_GFM
+}
+
+/// Renders a Markdown document, with the GitHub extensions, to an HTML
+/// fragment.
+///
+/// Raw HTML in the document is escaped, and the links and images with other
+/// schemes than [`SAFE_SCHEMES`] point to `#`, so that notes can't inject
+/// scripts in the page.
+pub fn render_markdown(markdown: &str) -> String {
+    let mut events = Vec::new();
+    let mut link_depth = 0u32;
+    let mut in_code_block = false;
+    for event in Parser::new_ext(markdown.trim_start_matches('\u{feff}'), options()) {
+        match event {
+            Event::Start(Tag::Link { link_type, dest_url, title, id }) => {
+                link_depth = link_depth.saturating_add(1);
+                events.push(Event::Start(Tag::Link {
+                    link_type,
+                    dest_url: to_safe_url(dest_url),
+                    title,
+                    id,
+                }));
+            }
+            Event::Start(Tag::Image { link_type, dest_url, title, id }) =>
+                events.push(Event::Start(Tag::Image {
+                    link_type,
+                    dest_url: to_safe_url(dest_url),
+                    title,
+                    id,
+                })),
+            Event::End(TagEnd::Link) => {
+                link_depth = link_depth.saturating_sub(1);
+                events.push(event);
+            }
+            Event::Start(Tag::CodeBlock(_)) => {
+                in_code_block = true;
+                events.push(event);
+            }
+            Event::End(TagEnd::CodeBlock) => {
+                in_code_block = false;
+                events.push(event);
+            }
+            Event::Html(raw) | Event::InlineHtml(raw) => events.push(Event::Text(raw)),
+            Event::Text(text) if link_depth == 0 && !in_code_block => autolink(&text, &mut events),
+            Event::Start(_)
+            | Event::End(_)
+            | Event::Text(_)
+            | Event::Code(_)
+            | Event::InlineMath(_)
+            | Event::DisplayMath(_)
+            | Event::FootnoteReference(_)
+            | Event::SoftBreak
+            | Event::HardBreak
+            | Event::Rule
+            | Event::TaskListMarker(_) => events.push(event),
+        }
+    }
+    let mut body = String::new();
+    html::push_html(&mut body, events.into_iter());
+    body
+}
+
+/// Replaces the URLs with unsafe schemes, like `javascript:`, with `#`.
+fn to_safe_url(url: CowStr<'_>) -> CowStr<'_> {
+    // Relative URLs have no `:` before their first `/`, `?` or `#`.
+    let scheme = url
+        .split(['/', '?', '#'])
+        .next()
+        .and_then(|start| start.split_once(':'))
+        .map(|(raw_scheme, _)| {
+            // Browsers ignore the whitespace and control characters in schemes.
+            raw_scheme
+                .chars()
+                .filter(|ch| !ch.is_ascii_whitespace() && !ch.is_control())
+                .collect::<String>()
+                .to_ascii_lowercase()
+        });
+    if scheme.is_some_and(|name| !SAFE_SCHEMES.contains(&name.as_str())) {
+        CowStr::from("#")
+    } else {
+        url
+    }
+}
+
+/// Turns the bare URLs of a text into links, like GFM's extended autolinks.
+fn autolink(text: &str, events: &mut Vec<Event<'_>>) {
+    let mut plain = String::new();
+    for piece in text.split_inclusive(char::is_whitespace) {
+        let url = piece
+            .trim_end()
+            .trim_start_matches(['(', '*', '_', '~'])
+            .trim_end_matches(['.', ',', ':', ';', '!', '?', '"', '\'', ')', '*', '_', '~']);
+        let Some((before, after)) = URL_PREFIXES
+            .iter()
+            .any(|prefix| url.len() > prefix.len() && url.starts_with(prefix))
+            .then(|| piece.split_once(url))
+            .flatten()
+        else {
+            plain.push_str(piece);
+            continue;
+        };
+        plain.push_str(before);
+        if !plain.is_empty() {
+            events.push(Event::Text(CowStr::from(mem::take(&mut plain))));
+        }
+        let href = if url.starts_with("www.") {
+            format!("http://{url}")
+        } else {
+            url.to_owned()
+        };
+        events.push(Event::Start(Tag::Link {
+            link_type: LinkType::Autolink,
+            dest_url: CowStr::from(href),
+            title: CowStr::from(""),
+            id: CowStr::from(""),
+        }));
+        events.push(Event::Text(CowStr::from(url.to_owned())));
+        events.push(Event::End(TagEnd::Link));
+        plain.push_str(after);
+    }
+    if !plain.is_empty() {
+        events.push(Event::Text(CowStr::from(plain)));
+    }
+}
+
+/// Escapes a text to insert it in HTML, as text or as an attribute value.
+pub fn escape(text: &str) -> String {
+    let mut escaped = String::new();
+    escape_html(FmtWriter(&mut escaped), text).unwrap_or_default();
+    escaped
+}
+
+/// Wraps an HTML fragment into a full page, with the stylesheet.
+pub fn html_page(title: &str, body: &str) -> String {
+    let escaped_title = escape(title);
+    format!(
+        "<!DOCTYPE html>\n\
+         <html lang=\"en\">\n\
+         <head>\n\
+         <meta charset=\"utf-8\">\n\
+         <meta name=\"viewport\" content=\"width=device-width, initial-scale=1\">\n\
+         <title>{escaped_title}</title>\n\
+         <style>\n{STYLE}</style>\n\
+         </head>\n\
+         <body>\n\
+         {body}\n\
+         </body>\n\
+         </html>\n"
+    )
+}
@@ -0,0 +1,156 @@
+use actix_web::http::StatusCode;
+use actix_web::test::{self, TestRequest};
+use md_viewer::NoteFormat;
+
+use crate::fake_google::FakeGoogle;
+use crate::{call, init_app, location, log_in, session_cookie};
+
+#[actix_web::test]
+async fn login_redirects_to_google_with_pkce() {
+    let fake = FakeGoogle::start();
+    let app = init_app(&fake, NoteFormat::Document).await;
+
+    let response =
+        test::call_service(&app, TestRequest::get().uri("/auth/login").to_request()).await;
+
+    assert_eq!(response.status(), StatusCode::FOUND);
+    let url = location(&response);
+    assert!(url.starts_with(&format!("{}/o/oauth2/auth?", fake.urls().accounts)));
+    assert!(url.contains("client_id=client-id"));
+    assert!(url.contains("code_challenge_method=S256"));
+    assert!(url.contains("state="));
+    let cookie = session_cookie(&response);
+    assert_eq!(cookie.secure(), Some(true));
+    assert_eq!(cookie.http_only(), Some(true));
+}
+
+#[actix_web::test]
+async fn callback_rejects_unknown_state() {
+    let fake = FakeGoogle::start();
+    let app = init_app(&fake, NoteFormat::Document).await;
+    let login = test::call_service(&app, TestRequest::get().uri("/auth/login").to_request()).await;
+
+    let response = test::call_service(
+        &app,
+        TestRequest::get()
+            .uri("/auth/callback/google?code=good-code&state=forged")
+            .cookie(session_cookie(&login))
+            .to_request(),
+    )
+    .await;
+
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+    assert_eq!(fake.drive().token_exchanges, 0);
+}
+
+#[actix_web::test]
+async fn callback_reports_a_rejected_code() {
+    let fake = FakeGoogle::start();
+    let app = init_app(&fake, NoteFormat::Document).await;
+    let login = test::call_service(&app, TestRequest::get().uri("/auth/login").to_request()).await;
+    let state = location(&login)
+        .split('&')
+        .find_map(|param| param.strip_prefix("state="))
+        .unwrap()
+        .to_owned();
+
+    let response = test::call_service(
+        &app,
+        TestRequest::get()
+            .uri(&format!("/auth/callback/google?code=bad-code&state={state}"))
+            .cookie(session_cookie(&login))
+            .to_request(),
+    )
+    .await;
+
+    assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+    let body = String::from_utf8(test::read_body(response).await.to_vec()).unwrap();
+    assert!(body.contains("Google refused to issue an access token"));
+    assert!(body.contains("invalid_grant"));
+}
+
+#[actix_web::test]
+async fn logged_in_browser_reads_its_profile() {
+    let fake = FakeGoogle::start();
+    let app = init_app(&fake, NoteFormat::Document).await;
+    let cookie = log_in(&app).await;
+
+    let (status, _, body) = call(&app, &cookie, TestRequest::get().uri("/auth/info")).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("someone@example.com"));
+}
+
+#[actix_web::test]
+async fn anonymous_browser_is_sent_to_login() {
+    let fake = FakeGoogle::start();
+    let app = init_app(&fake, NoteFormat::Document).await;
+
+    let response = test::call_service(&app, TestRequest::get().uri("/drive/ls").to_request()).await;
+
+    assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(location(&response), "/auth/login");
+}
+
+#[actix_web::test]
+async fn anonymous_browser_cant_render_previews() {
+    let fake = FakeGoogle::start();
+    let app = init_app(&fake, NoteFormat::Document).await;
+
+    let response = test::call_service(
+        &app,
+        TestRequest::post()
+            .uri("/view/preview")
+            .set_payload("# Title")
+            .to_request(),
+    )
+    .await;
+
+    assert_eq!(response.status(), StatusCode::TEMPORARY_REDIRECT);
+    assert_eq!(location(&response), "/auth/login");
+}
+
+#[actix_web::test]
+async fn rejected_token_is_refreshed_and_the_call_retried() {
+    let fake = FakeGoogle::start();
+    let app = init_app(&fake, NoteFormat::Document).await;
+    let cookie = log_in(&app).await;
+    fake.expire_tokens();
+
+    let (status, _, body) = call(&app, &cookie, TestRequest::get().uri("/auth/info")).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert!(body.contains("someone@example.com"));
+}
+
+#[actix_web::test]
+async fn logout_is_a_post() {
+    let fake = FakeGoogle::start();
+    let app = init_app(&fake, NoteFormat::Document).await;
+    let cookie = log_in(&app).await;
+
+    let (get_status, _, _) = call(&app, &cookie, TestRequest::get().uri("/auth/logout")).await;
+    let (status_between, _, _) = call(&app, &cookie, TestRequest::get().uri("/drive/ls")).await;
+    let (status, _, _) = call(&app, &cookie, TestRequest::post().uri("/auth/logout")).await;
+    let (status_after, _, _) = call(&app, &cookie, TestRequest::get().uri("/drive/ls")).await;
+
+    assert_eq!(get_status, StatusCode::NOT_FOUND);
+    assert_eq!(status_between, StatusCode::OK);
+    assert_eq!(status, StatusCode::OK);
+    assert!(fake.drive().revoked.is_empty());
+    assert_eq!(status_after, StatusCode::TEMPORARY_REDIRECT);
+}
+
+#[actix_web::test]
+async fn revoke_logs_out_and_revokes_the_refresh_token() {
+    let fake = FakeGoogle::start();
+    let app = init_app(&fake, NoteFormat::Document).await;
+    let cookie = log_in(&app).await;
+
+    let (status, _, _) = call(&app, &cookie, TestRequest::post().uri("/auth/revoke")).await;
+    let (status_after, _, _) = call(&app, &cookie, TestRequest::get().uri("/drive/ls")).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(fake.drive().revoked, ["refresh-token"]);
+    assert_eq!(status_after, StatusCode::TEMPORARY_REDIRECT);
+}
@@ -0,0 +1,774 @@
+use std::fs;
+use std::time::{Duration, Instant};
+
+use actix_web::http::header::{ETAG, IF_MATCH};
+use actix_web::http::{Method, StatusCode};
+use actix_web::rt::time::sleep;
+use actix_web::test::{self, TestRequest};
+use md_viewer::NoteFormat;
+use serde_json::Value;
+
+use crate::fake_google::FakeGoogle;
+use crate::{APP_FOLDER, call, init_app, init_indexed_app, init_synced_app, log_in, temp_dir};
+
+const DOCUMENT: &str = "application/vnd.google-apps.document";
+
+fn app_folder_id(fake: &FakeGoogle) -> String {
+    let drive = fake.drive();
+    let folders: Vec<_> = drive
+        .files
+        .iter()
+        .filter(|file| file.name == APP_FOLDER)
+        .collect();
+    assert_eq!(folders.len(), 1);
+    folders[0].id.clone()
+}
+
+fn file_names(listing: &str) -> Vec<String> {
+    let listing: Value = serde_json::from_str(listing).unwrap();
+    listing["files"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|file| file["name"].as_str().unwrap().to_owned())
+        .collect()
+}
+
+#[actix_web::test]
+async fn ls_creates_the_app_folder_once() {
+    let fake = FakeGoogle::start();
+    let app = init_app(&fake, NoteFormat::Document).await;
+    let cookie = log_in(&app).await;
+
+    let (status, _, body) = call(&app, &cookie, TestRequest::get().uri("/drive/ls")).await;
+    call(&app, &cookie, TestRequest::get().uri("/drive/ls")).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert!(file_names(&body).is_empty());
+    app_folder_id(&fake);
+}
+
+#[actix_web::test]
+async fn ls_skips_trashed_files_and_pages() {
+    let fake = FakeGoogle::start();
+    let app = init_app(&fake, NoteFormat::Document).await;
+    let cookie = log_in(&app).await;
+    call(&app, &cookie, TestRequest::get().uri("/drive/ls")).await;
+    let folder = app_folder_id(&fake);
+    for name in ["a", "b", "c"] {
+        fake.add_file(name, DOCUMENT, &folder, "");
+    }
+    let trashed = fake.add_file("trashed", DOCUMENT, &folder, "");
+    fake.drive()
+        .files
+        .iter_mut()
+        .find(|file| file.id == trashed)
+        .unwrap()
+        .trashed = true;
+
+    let (_, _, all) = call(&app, &cookie, TestRequest::get().uri("/drive/ls")).await;
+    let (_, _, page) = call(&app, &cookie, TestRequest::get().uri("/drive/ls?page_size=2")).await;
+    let (status, _, large) =
+        call(&app, &cookie, TestRequest::get().uri("/drive/ls?page_size=5000")).await;
+
+    assert_eq!(file_names(&all), ["a", "b", "c"]);
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(file_names(&large), ["a", "b", "c"]);
+    let page: Value = serde_json::from_str(&page).unwrap();
+    assert_eq!(page["files"].as_array().unwrap().len(), 2);
+    assert!(page["nextPageToken"].is_string());
+}
+
+#[actix_web::test]
+async fn search_escapes_quotes_in_names() {
+    let fake = FakeGoogle::start();
+    let app = init_app(&fake, NoteFormat::Document).await;
+    let cookie = log_in(&app).await;
+    call(&app, &cookie, TestRequest::get().uri("/drive/ls")).await;
+    let folder = app_folder_id(&fake);
+    fake.add_file("it's done", DOCUMENT, &folder, "");
+    fake.add_file("other", DOCUMENT, &folder, "");
+
+    let (status, _, body) =
+        call(&app, &cookie, TestRequest::get().uri("/drive/search?name=it%27s%20done")).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(file_names(&body), ["it's done"]);
+}
+
+#[actix_web::test]
+async fn document_round_trips_with_minimal_updates() {
+    let fake = FakeGoogle::start();
+    let app = init_app(&fake, NoteFormat::Document).await;
+    let cookie = log_in(&app).await;
+    let (_, _, id) =
+        call(&app, &cookie, TestRequest::get().uri("/drive/action/create/notes")).await;
+
+    let set = |content: &str| {
+        TestRequest::post()
+            .uri(&format!("/drive/action/set-content/{id}"))
+            .set_payload(content.to_owned())
+    };
+    let (status, _, _) = call(&app, &cookie, set("# Notes\nhello")).await;
+    call(&app, &cookie, set("# Notes\nhello world")).await;
+    let (_, headers, content) = call(
+        &app,
+        &cookie,
+        TestRequest::get().uri(&format!("/drive/action/get-content/{id}")),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(fake.file(&id).content, "# Notes\nhello world");
+    assert_eq!(content, "# Notes\nhello world");
+    assert_eq!(headers.get(ETAG).unwrap(), "\"rev-3\"");
+    let last_update = fake.drive().batch_updates.last().unwrap().clone();
+    assert_eq!(last_update["requests"].as_array().unwrap().len(), 1);
+    assert_eq!(last_update["requests"][0]["insertText"]["text"], " world");
+}
+
+#[actix_web::test]
+async fn saving_the_loaded_content_changes_nothing() {
+    let fake = FakeGoogle::start();
+    let app = init_app(&fake, NoteFormat::Document).await;
+    let cookie = log_in(&app).await;
+    call(&app, &cookie, TestRequest::get().uri("/drive/ls")).await;
+    let folder = app_folder_id(&fake);
+    let id = fake.add_file("note", DOCUMENT, &folder, "# Notes\n\n- one\n- two");
+    let (_, _, content) = call(
+        &app,
+        &cookie,
+        TestRequest::get().uri(&format!("/drive/action/get-content/{id}")),
+    )
+    .await;
+
+    let (status, _, _) = call(
+        &app,
+        &cookie,
+        TestRequest::post()
+            .uri(&format!("/drive/action/set-content/{id}"))
+            .set_payload(content.clone()),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(content, "# Notes\n\n- one\n- two");
+    assert!(fake.drive().batch_updates.is_empty());
+}
+
+#[actix_web::test]
+async fn stale_save_conflicts_with_the_current_content() {
+    let fake = FakeGoogle::start();
+    let app = init_app(&fake, NoteFormat::Document).await;
+    let cookie = log_in(&app).await;
+    call(&app, &cookie, TestRequest::get().uri("/drive/ls")).await;
+    let id = fake.add_file("shared", DOCUMENT, &app_folder_id(&fake), "first");
+    let (_, headers, _) = call(
+        &app,
+        &cookie,
+        TestRequest::get().uri(&format!("/drive/action/get-content/{id}")),
+    )
+    .await;
+    let revision = headers.get(ETAG).unwrap().clone();
+    fake.edit_file(&id, "edited elsewhere");
+
+    let (status, _, body) = call(
+        &app,
+        &cookie,
+        TestRequest::post()
+            .uri(&format!("/drive/action/set-content/{id}"))
+            .insert_header((IF_MATCH, revision))
+            .set_payload("mine"),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::CONFLICT);
+    let error: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(error["error"]["content"], "edited elsewhere");
+    assert_eq!(error["error"]["revision"], "rev-2");
+    assert_eq!(fake.file(&id).content, "edited elsewhere");
+}
+
+#[actix_web::test]
+async fn markdown_notes_round_trip_byte_for_byte() {
+    let fake = FakeGoogle::start();
+    let app = init_app(&fake, NoteFormat::Markdown).await;
+    let cookie = log_in(&app).await;
+    let content = "# Title\r\n\n- \"quotes\" -- and *lists*\n";
+
+    let (_, _, id) =
+        call(&app, &cookie, TestRequest::get().uri("/drive/action/create/notes")).await;
+    // An empty `If-Match` doesn't require any revision.
+    let (saved, _, _) = call(
+        &app,
+        &cookie,
+        TestRequest::post()
+            .uri(&format!("/drive/action/set-content/{id}"))
+            .insert_header((IF_MATCH, ""))
+            .set_payload(content),
+    )
+    .await;
+    let (status, _, body) = call(
+        &app,
+        &cookie,
+        TestRequest::get().uri(&format!("/drive/action/get-content/{id}")),
+    )
+    .await;
+
+    assert_eq!(saved, StatusCode::OK);
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, content);
+    let file = fake.file(&id);
+    assert_eq!(file.name, "notes.md");
+    assert_eq!(file.mime_type, "text/markdown");
+}
+
+#[actix_web::test]
+async fn missing_note_is_not_found() {
+    let fake = FakeGoogle::start();
+    let app = init_app(&fake, NoteFormat::Document).await;
+    let cookie = log_in(&app).await;
+
+    let (status, _, body) =
+        call(&app, &cookie, TestRequest::get().uri("/drive/action/get-content/missing")).await;
+
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    let error: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(error["error"]["kind"], "not_found");
+}
+
+#[actix_web::test]
+async fn transient_failures_are_retried() {
+    let fake = FakeGoogle::start();
+    let app = init_app(&fake, NoteFormat::Document).await;
+    let cookie = log_in(&app).await;
+    call(&app, &cookie, TestRequest::get().uri("/drive/ls")).await;
+    let folder = app_folder_id(&fake);
+    let id = fake.add_file("note", DOCUMENT, &folder, "text");
+    fake.fail_next(&[429, 503]);
+
+    let (status, _, body) = call(
+        &app,
+        &cookie,
+        TestRequest::get().uri(&format!("/drive/action/get-content/{id}")),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(body, "text");
+    assert!(fake.drive().failures.is_empty());
+}
+
+#[actix_web::test]
+async fn retries_give_up_when_google_asks_to_wait_too_long() {
+    let fake = FakeGoogle::start();
+    let app = init_app(&fake, NoteFormat::Document).await;
+    let cookie = log_in(&app).await;
+    call(&app, &cookie, TestRequest::get().uri("/drive/ls")).await;
+    let folder = app_folder_id(&fake);
+    let id = fake.add_file("note", DOCUMENT, &folder, "text");
+    fake.drive().retry_after = Some("Wed, 21 Oct 2099 07:28:00 GMT".to_owned());
+    fake.fail_next(&[503, 503]);
+
+    let (status, _, _) = call(
+        &app,
+        &cookie,
+        TestRequest::get().uri(&format!("/drive/action/get-content/{id}")),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+    assert_eq!(fake.drive().failures.len(), 1);
+}
+
+#[actix_web::test]
+async fn retries_give_up_after_the_last_attempt() {
+    let fake = FakeGoogle::start();
+    let app = init_app(&fake, NoteFormat::Document).await;
+    let cookie = log_in(&app).await;
+    call(&app, &cookie, TestRequest::get().uri("/drive/ls")).await;
+    let folder = app_folder_id(&fake);
+    let id = fake.add_file("note", DOCUMENT, &folder, "text");
+    fake.fail_next(&[503, 503, 503, 503]);
+
+    let (status, _, _) = call(
+        &app,
+        &cookie,
+        TestRequest::get().uri(&format!("/drive/action/get-content/{id}")),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+    assert_eq!(fake.drive().failures.len(), 1);
+}
+
+#[actix_web::test]
+async fn creations_are_not_retried() {
+    let fake = FakeGoogle::start();
+    let app = init_app(&fake, NoteFormat::Document).await;
+    let cookie = log_in(&app).await;
+    call(&app, &cookie, TestRequest::get().uri("/drive/ls")).await;
+    fake.fail_next(&[503]);
+
+    let (status, _, _) =
+        call(&app, &cookie, TestRequest::get().uri("/drive/action/create/note")).await;
+
+    assert_eq!(status, StatusCode::BAD_GATEWAY);
+    assert_eq!(fake.drive().files.len(), 1);
+}
+
+#[actix_web::test]
+async fn google_errors_are_not_served_as_listings() {
+    let fake = FakeGoogle::start();
+    let app = init_app(&fake, NoteFormat::Document).await;
+    let cookie = log_in(&app).await;
+    call(&app, &cookie, TestRequest::get().uri("/drive/ls")).await;
+    fake.fail_next(&[403]);
+
+    let (status, _, body) = call(&app, &cookie, TestRequest::get().uri("/drive/ls")).await;
+
+    assert_eq!(status, StatusCode::FORBIDDEN);
+    let body: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(body["error"]["kind"], "google");
+    assert_eq!(body["error"]["code"], 403);
+}
+
+#[actix_web::test]
+async fn notes_are_trashed_and_restored() {
+    let fake = FakeGoogle::start();
+    let app = init_app(&fake, NoteFormat::Document).await;
+    let cookie = log_in(&app).await;
+    call(&app, &cookie, TestRequest::get().uri("/drive/ls")).await;
+    let folder = app_folder_id(&fake);
+    let id = fake.add_file("note", DOCUMENT, &folder, "");
+
+    let (status, _, _) =
+        call(&app, &cookie, TestRequest::delete().uri(&format!("/drive/action/{id}"))).await;
+    let (_, _, listed) = call(&app, &cookie, TestRequest::get().uri("/drive/ls")).await;
+    let (_, _, trashed) = call(&app, &cookie, TestRequest::get().uri("/drive/trash")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert!(file_names(&listed).is_empty());
+    assert_eq!(file_names(&trashed), ["note"]);
+
+    let (status, _, _) =
+        call(&app, &cookie, TestRequest::post().uri(&format!("/drive/action/{id}/restore"))).await;
+    let (_, _, listed) = call(&app, &cookie, TestRequest::get().uri("/drive/ls")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(file_names(&listed), ["note"]);
+}
+
+#[actix_web::test]
+async fn trash_lists_the_notes_of_subfolders() {
+    let fake = FakeGoogle::start();
+    let app = init_app(&fake, NoteFormat::Document).await;
+    let cookie = log_in(&app).await;
+    call(&app, &cookie, TestRequest::get().uri("/drive/ls")).await;
+    let root = app_folder_id(&fake);
+    let projects = fake.add_file("projects", "application/vnd.google-apps.folder", &root, "");
+    let nested = fake.add_file("nested", DOCUMENT, &projects, "");
+    let top = fake.add_file("top", DOCUMENT, &root, "");
+    for id in [&nested, &top] {
+        call(&app, &cookie, TestRequest::delete().uri(&format!("/drive/action/{id}"))).await;
+    }
+
+    let (status, _, trashed) =
+        call(&app, &cookie, TestRequest::get().uri("/drive/trash?orderBy=name")).await;
+    let (_, _, first) =
+        call(&app, &cookie, TestRequest::get().uri("/drive/trash?page_size=1")).await;
+    let first = serde_json::from_str::<Value>(&first).unwrap();
+    let next = first["nextPageToken"].as_str().unwrap();
+    let (_, _, second) = call(
+        &app,
+        &cookie,
+        TestRequest::get().uri(&format!("/drive/trash?page_size=1&page_token={next}")),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(file_names(&trashed), ["nested", "top"]);
+    let mut paged = [file_names(&first.to_string()), file_names(&second)].concat();
+    paged.sort();
+    assert_eq!(paged, ["nested", "top"]);
+}
+
+#[actix_web::test]
+async fn deleting_succeeds_when_the_answer_was_lost() {
+    let fake = FakeGoogle::start();
+    let app = init_app(&fake, NoteFormat::Document).await;
+    let cookie = log_in(&app).await;
+    call(&app, &cookie, TestRequest::get().uri("/drive/ls")).await;
+    let folder = app_folder_id(&fake);
+    let id = fake.add_file("note", DOCUMENT, &folder, "");
+    fake.lose_next_answer(Method::DELETE);
+
+    let (status, _, _) =
+        call(&app, &cookie, TestRequest::post().uri(&format!("/drive/action/delete/{id}"))).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert!(fake.drive().files.iter().all(|file| file.id != id));
+}
+
+#[actix_web::test]
+async fn files_outside_the_app_folder_are_left_alone() {
+    let fake = FakeGoogle::start();
+    let app = init_app(&fake, NoteFormat::Document).await;
+    let cookie = log_in(&app).await;
+    call(&app, &cookie, TestRequest::get().uri("/drive/ls")).await;
+    let other = fake.add_file("private", DOCUMENT, "root", "");
+
+    let (trash_status, _, _) =
+        call(&app, &cookie, TestRequest::delete().uri(&format!("/drive/action/{other}"))).await;
+    let (delete_status, _, _) =
+        call(&app, &cookie, TestRequest::post().uri(&format!("/drive/action/delete/{other}")))
+            .await;
+
+    assert_eq!(trash_status, StatusCode::NOT_FOUND);
+    assert_eq!(delete_status, StatusCode::NOT_FOUND);
+    assert!(!fake.file(&other).trashed);
+}
+
+#[actix_web::test]
+async fn files_outside_the_app_folder_are_not_renamed() {
+    let fake = FakeGoogle::start();
+    let app = init_app(&fake, NoteFormat::Document).await;
+    let cookie = log_in(&app).await;
+    call(&app, &cookie, TestRequest::get().uri("/drive/ls")).await;
+    let other = fake.add_file("private", DOCUMENT, "root", "");
+
+    let (status, _, _) = call(
+        &app,
+        &cookie,
+        TestRequest::post()
+            .uri(&format!("/drive/action/rename/{other}"))
+            .set_payload("renamed"),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::NOT_FOUND);
+    assert_eq!(fake.file(&other).name, "private");
+}
+
+#[actix_web::test]
+async fn notes_are_organised_in_subfolders() {
+    let fake = FakeGoogle::start();
+    let app = init_app(&fake, NoteFormat::Document).await;
+    let cookie = log_in(&app).await;
+    let (_, _, folder) =
+        call(&app, &cookie, TestRequest::post().uri("/drive/action/create-folder/projects")).await;
+    let folder: Value = serde_json::from_str(&folder).unwrap();
+    let folder = folder["id"].as_str().unwrap();
+    let (status, _, note) = call(
+        &app,
+        &cookie,
+        TestRequest::get().uri(&format!("/drive/action/create/plan?folder={folder}")),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    let (_, _, root) = call(&app, &cookie, TestRequest::get().uri("/drive/ls")).await;
+    let (_, _, projects) =
+        call(&app, &cookie, TestRequest::get().uri(&format!("/drive/ls?folder={folder}"))).await;
+    assert_eq!(file_names(&root), ["projects"]);
+    assert_eq!(file_names(&projects), ["plan"]);
+
+    let (status, _, _) =
+        call(&app, &cookie, TestRequest::post().uri(&format!("/drive/action/move/{note}"))).await;
+    let (_, _, renamed) = call(
+        &app,
+        &cookie,
+        TestRequest::post()
+            .uri(&format!("/drive/action/rename/{note}"))
+            .set_payload("roadmap"),
+    )
+    .await;
+    let (_, _, root) = call(&app, &cookie, TestRequest::get().uri("/drive/ls")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(serde_json::from_str::<Value>(&renamed).unwrap()["name"], "roadmap");
+    assert_eq!(file_names(&root), ["projects", "roadmap"]);
+}
+
+#[actix_web::test]
+async fn folders_outside_the_app_folder_are_not_listed() {
+    let fake = FakeGoogle::start();
+    let app = init_app(&fake, NoteFormat::Document).await;
+    let cookie = log_in(&app).await;
+    call(&app, &cookie, TestRequest::get().uri("/drive/ls")).await;
+    let other = fake.add_file("private", "application/vnd.google-apps.folder", "root", "");
+
+    let (status, _, _) =
+        call(&app, &cookie, TestRequest::get().uri(&format!("/drive/ls?folder={other}"))).await;
+
+    assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[actix_web::test]
+async fn tree_nests_folders_down_to_the_depth_limit() {
+    const FOLDER: &str = "application/vnd.google-apps.folder";
+    let fake = FakeGoogle::start();
+    let app = init_app(&fake, NoteFormat::Document).await;
+    let cookie = log_in(&app).await;
+    call(&app, &cookie, TestRequest::get().uri("/drive/ls")).await;
+    let root = app_folder_id(&fake);
+    let projects = fake.add_file("projects", FOLDER, &root, "");
+    let archive = fake.add_file("archive", FOLDER, &projects, "");
+    fake.add_file("old.md", "text/markdown", &archive, "# Old");
+    fake.add_file("plan", DOCUMENT, &projects, "");
+    fake.add_file("inbox", DOCUMENT, &root, "");
+
+    let (status, _, full) = call(&app, &cookie, TestRequest::get().uri("/drive/tree")).await;
+    let (_, _, shallow) = call(&app, &cookie, TestRequest::get().uri("/drive/tree?depth=1")).await;
+
+    assert_eq!(status, StatusCode::OK);
+    let full: Value = serde_json::from_str(&full).unwrap();
+    assert_eq!(full["id"], root);
+    assert_eq!(full["files"][0]["name"], "projects");
+    assert_eq!(full["files"][1]["name"], "inbox");
+    let archive_node = &full["files"][0]["children"][0];
+    assert_eq!(archive_node["name"], "archive");
+    assert_eq!(archive_node["children"][0]["name"], "old.md");
+    assert_eq!(archive_node["children"][0]["size"], "5");
+    assert!(archive_node["children"][0]["modifiedTime"].is_string());
+    let shallow: Value = serde_json::from_str(&shallow).unwrap();
+    assert!(shallow["files"][0].get("children").is_none());
+}
+
+#[actix_web::test]
+async fn metadata_has_the_selected_fields() {
+    let fake = FakeGoogle::start();
+    let app = init_app(&fake, NoteFormat::Document).await;
+    let cookie = log_in(&app).await;
+    call(&app, &cookie, TestRequest::get().uri("/drive/ls")).await;
+    let folder = app_folder_id(&fake);
+    let id = fake.add_file("note.md", "text/markdown", &folder, "# Note");
+
+    let (status, _, full) =
+        call(&app, &cookie, TestRequest::get().uri(&format!("/drive/action/metadata/{id}"))).await;
+    let (_, _, selected) = call(
+        &app,
+        &cookie,
+        TestRequest::get().uri(&format!("/drive/action/metadata/{id}?fields=id,name,size")),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    let full: Value = serde_json::from_str(&full).unwrap();
+    assert_eq!(full["size"], "6");
+    assert_eq!(full["parents"][0], folder);
+    assert_eq!(full["owners"][0]["emailAddress"], "someone@example.com");
+    assert_eq!(full["lastModifyingUser"]["displayName"], "Someone");
+    assert_eq!(full["starred"], false);
+    assert_eq!(full["version"], "1");
+    assert!(full["modifiedTime"].is_string());
+    let selected: Value = serde_json::from_str(&selected).unwrap();
+    assert_eq!(selected["name"], "note.md");
+    assert_eq!(selected["size"], "6");
+    assert!(selected.get("owners").is_none());
+}
+
+#[actix_web::test]
+async fn listings_are_sorted_and_filtered() {
+    let fake = FakeGoogle::start();
+    let app = init_app(&fake, NoteFormat::Document).await;
+    let cookie = log_in(&app).await;
+    call(&app, &cookie, TestRequest::get().uri("/drive/ls")).await;
+    let folder = app_folder_id(&fake);
+    fake.add_file("alpha.md", "text/markdown", &folder, "");
+    let starred = fake.add_file("beta.md", "text/markdown", &folder, "");
+    fake.add_file("alps", DOCUMENT, &folder, "");
+    fake.add_file("Alpine.md", "text/markdown", &folder, "");
+    fake.add_file("my alps.md", "text/markdown", &folder, "");
+    fake.drive()
+        .files
+        .iter_mut()
+        .filter(|file| file.id == starred)
+        .for_each(|file| file.starred = true);
+
+    let (status, _, sorted) =
+        call(&app, &cookie, TestRequest::get().uri("/drive/ls?orderBy=name%20desc")).await;
+    let (_, _, documents) =
+        call(&app, &cookie, TestRequest::get().uri("/drive/ls?type=document")).await;
+    let (_, _, starred_only) =
+        call(&app, &cookie, TestRequest::get().uri("/drive/ls?starred=true")).await;
+    let (_, _, prefixed) =
+        call(&app, &cookie, TestRequest::get().uri("/drive/ls?name_prefix=al&orderBy=name")).await;
+    let (invalid, _, _) =
+        call(&app, &cookie, TestRequest::get().uri("/drive/ls?orderBy=size")).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(file_names(&sorted), ["my alps.md", "beta.md", "alps", "alpha.md", "Alpine.md"]);
+    assert_eq!(file_names(&documents), ["alps"]);
+    assert_eq!(file_names(&starred_only), ["beta.md"]);
+    assert_eq!(file_names(&prefixed), ["Alpine.md", "alpha.md", "alps"]);
+    assert_eq!(invalid, StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn full_text_search_ranks_and_pages_the_notes_of_the_app_folder() {
+    let fake = FakeGoogle::start();
+    let app = init_app(&fake, NoteFormat::Document).await;
+    let cookie = log_in(&app).await;
+    call(&app, &cookie, TestRequest::get().uri("/drive/ls")).await;
+    let root = app_folder_id(&fake);
+    let projects = fake.add_file("projects", "application/vnd.google-apps.folder", &root, "");
+    fake.add_file(
+        "animals.md",
+        "text/markdown",
+        &projects,
+        "# Animals\n\nThe quick brown Fox jumps.",
+    );
+    fake.add_file("fox.md", "text/markdown", &root, "Only the title matches");
+    fake.add_file("elsewhere.md", "text/markdown", "root", "Another fox");
+    fake.add_file("fox figures", "application/vnd.google-apps.spreadsheet", &root, "fox");
+
+    let (status, _, first) =
+        call(&app, &cookie, TestRequest::get().uri("/drive/search?q=fox&page_size=1")).await;
+    let first: Value = serde_json::from_str(&first).unwrap();
+    let token = first["nextPageToken"].as_str().unwrap();
+    let (_, _, second) = call(
+        &app,
+        &cookie,
+        TestRequest::get().uri(&format!("/drive/search?q=fox&page_size=1&page_token={token}")),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(first["files"][0]["name"], "fox.md");
+    assert!(first["files"][0].get("snippet").is_none());
+    let second: Value = serde_json::from_str(&second).unwrap();
+    assert_eq!(second["files"][0]["name"], "animals.md");
+    assert_eq!(
+        second["files"][0]["snippet"],
+        "# Animals The quick brown <mark>Fox</mark> jumps."
+    );
+    assert!(second.get("nextPageToken").is_none());
+}
+
+#[actix_web::test]
+async fn indexed_search_follows_saves_and_external_edits() {
+    let fake = FakeGoogle::start();
+    let index_dir = temp_dir("index");
+    let app = init_indexed_app(&fake, index_dir.clone()).await;
+    let cookie = log_in(&app).await;
+    call(&app, &cookie, TestRequest::get().uri("/drive/ls")).await;
+    let root = app_folder_id(&fake);
+    let projects = fake.add_file("projects", "application/vnd.google-apps.folder", &root, "");
+    let rust = fake.add_file(
+        "rust.md",
+        "text/markdown",
+        &projects,
+        "# Borrow checker\n\nLifetimes. #rust",
+    );
+    let library = fake.add_file("library.md", "text/markdown", &root, "Borrowing books.");
+    fake.add_file("outside.md", "text/markdown", "root", "borrow checker books");
+    fake.add_file("figures", "application/vnd.google-apps.spreadsheet", &root, "borrow");
+    let search = async |query: &str| {
+        let (status, _, results) =
+            call(&app, &cookie, TestRequest::get().uri(&format!("/drive/search?q={query}"))).await;
+        assert_eq!(status, StatusCode::OK);
+        serde_json::from_str::<Value>(&results).unwrap()
+    };
+
+    let prefixed = search("borrow*").await;
+    assert_eq!(file_names(&prefixed.to_string()), ["rust.md", "library.md"]);
+    assert_eq!(
+        prefixed["files"][0]["snippet"],
+        "# <mark>Borrow</mark> checker Lifetimes. #rust"
+    );
+    let phrase = search("%22borrow%20checker%22").await;
+    assert_eq!(file_names(&phrase.to_string()), ["rust.md"]);
+    assert_eq!(fs::read_dir(&index_dir).unwrap().count(), 1);
+
+    call(
+        &app,
+        &cookie,
+        TestRequest::post()
+            .uri(&format!("/drive/action/set-content/{library}"))
+            .set_payload("Nothing left"),
+    )
+    .await;
+    assert!(file_names(&search("books").await.to_string()).is_empty());
+    fake.edit_file(&rust, "# Notes\n\nbooks everywhere");
+    let edited = search("books").await;
+    assert_eq!(file_names(&edited.to_string()), ["rust.md"]);
+    assert_eq!(edited["files"][0]["snippet"], "# Notes <mark>books</mark> everywhere");
+}
+
+#[actix_web::test]
+async fn debug_page_hides_the_indexed_notes() {
+    let fake = FakeGoogle::start();
+    let app = init_indexed_app(&fake, temp_dir("index-debug")).await;
+    let cookie = log_in(&app).await;
+    call(&app, &cookie, TestRequest::get().uri("/drive/ls")).await;
+    let root = app_folder_id(&fake);
+    fake.add_file("secret.md", "text/markdown", &root, "private diary");
+    let (_, _, results) =
+        call(&app, &cookie, TestRequest::get().uri("/drive/search?q=diary")).await;
+    assert_eq!(file_names(&results), ["secret.md"]);
+
+    let response = test::call_service(&app, TestRequest::get().uri("/debug").to_request()).await;
+    let body = String::from_utf8(test::read_body(response).await.to_vec()).unwrap();
+
+    assert!(body.contains("SearchIndex"));
+    assert!(!body.contains("diary"));
+    assert!(!body.contains("secret.md"));
+}
+
+#[actix_web::test]
+async fn listings_are_served_from_the_synced_metadata() {
+    let fake = FakeGoogle::start();
+    let app = init_synced_app(&fake, Duration::from_secs(60)).await;
+    let cookie = log_in(&app).await;
+    call(&app, &cookie, TestRequest::get().uri("/drive/ls")).await;
+    let root = app_folder_id(&fake);
+    fake.add_file("external", DOCUMENT, &root, "");
+
+    fake.fail_next(&[400]);
+    let (status, _, cached) = call(&app, &cookie, TestRequest::get().uri("/drive/ls")).await;
+    fake.drive().failures.clear();
+    assert_eq!(status, StatusCode::OK);
+    assert!(file_names(&cached).is_empty());
+
+    call(&app, &cookie, TestRequest::get().uri("/drive/action/create/mine")).await;
+    let (_, _, synced) = call(&app, &cookie, TestRequest::get().uri("/drive/ls")).await;
+    let (_, _, paged) =
+        call(&app, &cookie, TestRequest::get().uri("/drive/ls?page_size=1&page_token=1")).await;
+    assert_eq!(file_names(&synced), ["external", "mine"]);
+    assert_eq!(file_names(&paged), ["mine"]);
+}
+
+#[actix_web::test]
+async fn folders_moved_in_are_synced_with_their_contents() {
+    const FOLDER: &str = "application/vnd.google-apps.folder";
+    let fake = FakeGoogle::start();
+    let app = init_synced_app(&fake, Duration::from_millis(10)).await;
+    let cookie = log_in(&app).await;
+    call(&app, &cookie, TestRequest::get().uri("/drive/tree")).await;
+    let root = app_folder_id(&fake);
+    let archive = fake.add_file("archive", FOLDER, "root", "");
+    fake.add_file("old", DOCUMENT, &archive, "");
+
+    {
+        let mut drive = fake.drive();
+        drive
+            .files
+            .iter_mut()
+            .find(|file| file.id == archive)
+            .unwrap()
+            .parents = vec![root.clone()];
+        drive.changes.push(archive.clone());
+    }
+    // Polled, as the worker syncs in the background.
+    let deadline = Instant::now() + Duration::from_secs(10);
+    let tree = loop {
+        let (status, _, tree) = call(&app, &cookie, TestRequest::get().uri("/drive/tree")).await;
+        assert_eq!(status, StatusCode::OK);
+        let tree: Value = serde_json::from_str(&tree).unwrap();
+        if tree["files"][0]["id"] == archive.as_str() || Instant::now() > deadline {
+            break tree;
+        }
+        sleep(Duration::from_millis(10)).await;
+    };
+
+    assert_eq!(tree["files"][0]["id"], archive);
+    assert_eq!(tree["files"][0]["children"][0]["name"], "old");
+}
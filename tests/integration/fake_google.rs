@@ -0,0 +1,817 @@
+//! In-process fake of the Google endpoints used by the server: OAuth token
+//! exchange, user info, Drive files and Docs documents.
+
+use std::collections::{HashMap, VecDeque};
+use std::pin::Pin;
+use std::sync::{Mutex, MutexGuard};
+
+use actix_web::dev::{Service as _, ServiceResponse};
+use actix_web::http::Method;
+use actix_web::{App, HttpRequest, HttpResponse, HttpServer, web};
+use md_viewer::GoogleUrls;
+use serde::Deserialize;
+use serde_json::{Value, json};
+
+pub const GOOD_CODE: &str = "good-code";
+const REFRESH_TOKEN: &str = "refresh-token";
+const DOCUMENT_MIME_TYPE: &str = "application/vnd.google-apps.document";
+
+#[derive(Clone)]
+pub struct File {
+    pub id: String,
+    pub name: String,
+    pub mime_type: String,
+    pub parents: Vec<String>,
+    pub trashed: bool,
+    /// Text of the file, without the final newline Docs keeps in documents.
+    pub content: String,
+    pub revision: u32,
+    pub starred: bool,
+}
+
+impl File {
+    fn is_document(&self) -> bool {
+        self.mime_type == DOCUMENT_MIME_TYPE
+    }
+
+    fn revision_id(&self) -> String {
+        format!("rev-{}", self.revision)
+    }
+
+    fn to_json(&self) -> Value {
+        let mut file = json!({
+            "id": self.id,
+            "kind": "drive#file",
+            "mimeType": self.mime_type,
+            "name": self.name,
+            "parents": self.parents,
+            "trashed": self.trashed,
+            "createdTime": "2025-01-01T00:00:00Z",
+            "modifiedTime": format!("2025-01-01T00:00:{:02}Z", self.revision),
+            "owners": [{ "displayName": "Someone", "emailAddress": "someone@example.com" }],
+            "lastModifyingUser": { "displayName": "Someone" },
+            "starred": self.starred,
+            "webViewLink": format!("https://docs.google.com/document/d/{}/edit", self.id),
+            "version": self.revision.to_string(),
+        });
+        if !self.is_document() {
+            file["headRevisionId"] = self.revision_id().into();
+        }
+        if !self.mime_type.starts_with("application/vnd.google-apps.") {
+            file["size"] = self.content.len().to_string().into();
+        }
+        file
+    }
+
+    /// Body of the document as returned by `documents.get`, indexed in UTF-16
+    /// code units after the initial section break.
+    fn to_document(&self) -> Value {
+        let mut content = vec![json!({ "endIndex": 1, "sectionBreak": {} })];
+        let mut index = 1;
+        for line in format!("{}\n", self.content).split_inclusive('\n') {
+            let end = index + line.encode_utf16().count();
+            content.push(json!({
+                "startIndex": index,
+                "endIndex": end,
+                "paragraph": {
+                    "elements": [{
+                        "startIndex": index,
+                        "endIndex": end,
+                        "textRun": { "content": line },
+                    }],
+                },
+            }));
+            index = end;
+        }
+        json!({
+            "documentId": self.id,
+            "revisionId": self.revision_id(),
+            "body": { "content": content },
+        })
+    }
+}
+
+#[derive(Default)]
+pub struct Drive {
+    pub files: Vec<File>,
+    access_tokens: Vec<String>,
+    /// Requests of every successful `batchUpdate`.
+    pub batch_updates: Vec<Value>,
+    pub revoked: Vec<String>,
+    pub token_exchanges: usize,
+    /// Statuses answered to the next requests instead of handling them.
+    pub failures: VecDeque<u16>,
+    /// `Retry-After` of the failures, or none to retry at once.
+    pub retry_after: Option<String>,
+    /// Method of the next request handled, whose answer is then lost to a
+    /// 503.
+    pub lost_answer: Option<Method>,
+    /// Ids of the files changed, in order, the page tokens of the changes
+    /// feed being indices in it.
+    pub changes: Vec<String>,
+}
+
+impl Drive {
+    fn issue_token(&mut self) -> String {
+        let token = format!("access-{}", self.access_tokens.len());
+        self.access_tokens.push(token.clone());
+        token
+    }
+
+    fn add_file(
+        &mut self,
+        name: &str,
+        mime_type: &str,
+        parents: Vec<String>,
+        content: &str,
+    ) -> &File {
+        let id = format!("file-{}", self.files.len());
+        self.changes.push(id.clone());
+        self.files.push(File {
+            id,
+            name: name.to_owned(),
+            mime_type: mime_type.to_owned(),
+            parents,
+            trashed: false,
+            content: content.to_owned(),
+            revision: 1,
+            starred: false,
+        });
+        self.files.last().unwrap()
+    }
+
+    fn file_mut(&mut self, id: &str) -> Option<&mut File> {
+        self.files.iter_mut().find(|file| file.id == id)
+    }
+}
+
+type State = web::Data<Mutex<Drive>>;
+
+pub struct FakeGoogle {
+    url: String,
+    state: State,
+}
+
+impl FakeGoogle {
+    /// Starts the fake on a free port of the loopback interface.
+    pub fn start() -> Self {
+        let state: State = web::Data::new(Mutex::new(Drive::default()));
+        let app_state = state.clone();
+        let server = HttpServer::new(move || {
+            App::new()
+                .app_data(app_state.clone())
+                .wrap_fn(|req, srv| -> ResponseFuture {
+                    let (failure, retry_after, lost) = req
+                        .app_data::<State>()
+                        .map(|state| {
+                            let mut drive = state.lock().unwrap();
+                            let lost = drive.lost_answer.as_ref() == Some(req.method());
+                            if lost {
+                                drive.lost_answer = None;
+                            }
+                            (drive.failures.pop_front(), drive.retry_after.clone(), lost)
+                        })
+                        .unwrap_or_default();
+                    let retry_after = retry_after.unwrap_or_else(|| "0".to_owned());
+                    match failure {
+                        Some(code) => {
+                            let response = req.into_response(transient_error(code, &retry_after));
+                            Box::pin(async move { Ok(response) })
+                        }
+                        None if lost => {
+                            let handled = srv.call(req);
+                            Box::pin(async move {
+                                let response = handled.await?;
+                                Ok(response.into_response(transient_error(503, &retry_after)))
+                            })
+                        }
+                        None => Box::pin(srv.call(req)),
+                    }
+                })
+                .configure(routes)
+        })
+        .workers(1)
+        .bind(("127.0.0.1", 0))
+        .unwrap();
+        let url = format!("http://{}", server.addrs()[0]);
+        actix_web::rt::spawn(server.run());
+        Self { url, state }
+    }
+
+    pub fn urls(&self) -> GoogleUrls {
+        GoogleUrls {
+            accounts: self.url.clone(),
+            apis: self.url.clone(),
+            docs: self.url.clone(),
+            oauth: self.url.clone(),
+        }
+    }
+
+    pub fn drive(&self) -> MutexGuard<'_, Drive> {
+        self.state.lock().unwrap()
+    }
+
+    /// Adds a file, as if it was created outside of the server.
+    pub fn add_file(&self, name: &str, mime_type: &str, parent: &str, content: &str) -> String {
+        self.drive()
+            .add_file(name, mime_type, vec![parent.to_owned()], content)
+            .id
+            .clone()
+    }
+
+    /// Edits a file, as if a collaborator changed it.
+    pub fn edit_file(&self, id: &str, content: &str) {
+        let mut drive = self.drive();
+        let file = drive.file_mut(id).unwrap();
+        content.clone_into(&mut file.content);
+        file.revision += 1;
+        drive.changes.push(id.to_owned());
+    }
+
+    pub fn file(&self, id: &str) -> File {
+        self.drive()
+            .files
+            .iter()
+            .find(|file| file.id == id)
+            .unwrap()
+            .clone()
+    }
+
+    /// Fails the next requests with these statuses, as if Google was rate
+    /// limiting or unavailable.
+    pub fn fail_next(&self, codes: &[u16]) {
+        self.drive().failures.extend(codes);
+    }
+
+    /// Handles the next request with this method, but answers it with a 503
+    /// as if its answer was lost on the way back.
+    pub fn lose_next_answer(&self, method: Method) {
+        self.drive().lost_answer = Some(method);
+    }
+
+    /// Rejects every access token issued so far, as if they had expired.
+    pub fn expire_tokens(&self) {
+        self.drive().access_tokens.clear();
+    }
+}
+
+fn routes(cfg: &mut web::ServiceConfig) {
+    cfg.route("/token", web::post().to(token))
+        .route("/revoke", web::post().to(revoke))
+        .route("/oauth2/v2/userinfo", web::get().to(userinfo))
+        .route("/drive/v3/changes", web::get().to(list_changes))
+        .route("/drive/v3/changes/startPageToken", web::get().to(start_page_token))
+        .route("/drive/v3/files", web::get().to(list_files))
+        .route("/drive/v3/files", web::post().to(create_file))
+        .route("/drive/v3/files/{id}", web::get().to(get_file))
+        .route("/drive/v3/files/{id}", web::patch().to(patch_file))
+        .route("/drive/v3/files/{id}", web::delete().to(delete_file))
+        .route("/drive/v3/files/{id}/export", web::get().to(export_file))
+        .route("/upload/drive/v3/files", web::post().to(upload_file))
+        .route("/upload/drive/v3/files/{id}", web::patch().to(update_file))
+        .route("/v1/documents/{id}", web::get().to(get_document))
+        .route("/v1/documents/{id}", web::post().to(batch_update));
+}
+
+type ResponseFuture = Pin<Box<dyn Future<Output = Result<ServiceResponse, actix_web::Error>>>>;
+
+/// Rate limit or server error, asking to retry right away.
+fn transient_error(code: u16, retry_after: &str) -> HttpResponse {
+    let mut response = google_error(code, "UNAVAILABLE", "Try again later.");
+    response.headers_mut().insert(
+        actix_web::http::header::RETRY_AFTER,
+        actix_web::http::header::HeaderValue::from_str(retry_after).unwrap(),
+    );
+    response
+}
+
+fn google_error(code: u16, status: &str, message: &str) -> HttpResponse {
+    HttpResponse::build(actix_web::http::StatusCode::from_u16(code).unwrap()).json(json!({
+        "error": { "code": code, "message": message, "status": status }
+    }))
+}
+
+/// Checks the bearer token of the request.
+fn authorise<'state>(
+    req: &HttpRequest,
+    state: &'state State,
+) -> Result<MutexGuard<'state, Drive>, HttpResponse> {
+    let drive = state.lock().unwrap();
+    let token = req
+        .headers()
+        .get("Authorization")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    if token.is_some_and(|token| drive.access_tokens.iter().any(|valid| valid == token)) {
+        Ok(drive)
+    } else {
+        Err(google_error(401, "UNAUTHENTICATED", "Invalid Credentials"))
+    }
+}
+
+macro_rules! authorise {
+    ($req:ident, $state:ident) => {
+        match authorise(&$req, &$state) {
+            Ok(drive) => drive,
+            Err(response) => return response,
+        }
+    };
+}
+
+async fn token(state: State, form: web::Form<HashMap<String, String>>) -> HttpResponse {
+    let mut drive = state.lock().unwrap();
+    let oauth_error = |description: &str| {
+        HttpResponse::BadRequest().json(json!({
+            "error": "invalid_grant",
+            "error_description": description,
+        }))
+    };
+    match form.get("grant_type").map(String::as_str) {
+        Some("authorization_code") => {
+            if form.get("code").map(String::as_str) != Some(GOOD_CODE) {
+                return oauth_error("Malformed auth code.");
+            }
+            if form.get("code_verifier").is_none_or(String::is_empty) {
+                return oauth_error("Missing code verifier.");
+            }
+            drive.token_exchanges += 1;
+            HttpResponse::Ok().json(json!({
+                "access_token": drive.issue_token(),
+                "expires_in": 3599,
+                "id_token": "id-token",
+                "refresh_token": REFRESH_TOKEN,
+                "scope": "email profile openid",
+                "token_type": "Bearer",
+            }))
+        }
+        Some("refresh_token") => {
+            if form.get("refresh_token").map(String::as_str) != Some(REFRESH_TOKEN) {
+                return oauth_error("Token has been expired or revoked.");
+            }
+            HttpResponse::Ok().json(json!({
+                "access_token": drive.issue_token(),
+                "expires_in": 3599,
+            }))
+        }
+        _ => oauth_error("Unsupported grant type."),
+    }
+}
+
+async fn revoke(state: State, form: web::Form<HashMap<String, String>>) -> HttpResponse {
+    let mut drive = state.lock().unwrap();
+    drive
+        .revoked
+        .push(form.get("token").cloned().unwrap_or_default());
+    drive.access_tokens.clear();
+    HttpResponse::Ok().finish()
+}
+
+async fn userinfo(req: HttpRequest, state: State) -> HttpResponse {
+    let _drive = authorise!(req, state);
+    HttpResponse::Ok().json(json!({ "email": "someone@example.com", "name": "Someone" }))
+}
+
+/// Reads a quoted value of Drive's search syntax, returning it with the rest
+/// of the query.
+fn parse_quoted(query: &str) -> Option<(String, &str)> {
+    let mut value = String::new();
+    let mut chars = query.strip_prefix('\'')?.char_indices();
+    while let Some((index, char)) = chars.next() {
+        match char {
+            '\\' => value.push(chars.next()?.1),
+            '\'' => return Some((value, &query[index + 2..])),
+            _ => value.push(char),
+        }
+    }
+    None
+}
+
+/// Condition of a search on the files.
+type Filter = Box<dyn Fn(&File) -> bool>;
+
+/// Parses the search syntax supported by the fake into a filter.
+fn parse_query(mut query: &str) -> Option<Vec<Filter>> {
+    let mut filters: Vec<Filter> = Vec::new();
+    while !query.is_empty() {
+        if let Some(rest) = query.strip_prefix("trashed = false") {
+            filters.push(Box::new(|file| !file.trashed));
+            query = rest;
+        } else if let Some(rest) = query.strip_prefix("trashed = true") {
+            filters.push(Box::new(|file| file.trashed));
+            query = rest;
+        } else if let Some(rest) = query.strip_prefix("starred = true") {
+            filters.push(Box::new(|file| file.starred));
+            query = rest;
+        } else if let Some(mut rest) = query.strip_prefix('(') {
+            let mut alternatives: Vec<Filter> = Vec::new();
+            loop {
+                if let Some(after) = rest.strip_prefix("mimeType = ") {
+                    let (mime_type, after) = parse_quoted(after)?;
+                    alternatives.push(Box::new(move |file| file.mime_type == mime_type));
+                    rest = after;
+                } else {
+                    let (parent, after) = parse_quoted(rest)?;
+                    alternatives.push(Box::new(move |file| file.parents.contains(&parent)));
+                    rest = after.strip_prefix(" in parents")?;
+                }
+                match rest.strip_prefix(" or ") {
+                    Some(after) => rest = after,
+                    None => break,
+                }
+            }
+            filters.push(Box::new(move |file| {
+                alternatives.iter().any(|alternative| alternative(file))
+            }));
+            query = rest.strip_prefix(')')?;
+        } else if query.starts_with('\'') {
+            let (parent, rest) = parse_quoted(query)?;
+            filters.push(Box::new(move |file| file.parents.contains(&parent)));
+            query = rest.strip_prefix(" in parents")?;
+        } else {
+            let (field, rest) = [
+                "name = ",
+                "name contains ",
+                "mimeType = ",
+                "mimeType != ",
+                "fullText contains ",
+                "modifiedTime > ",
+            ]
+            .into_iter()
+            .find_map(|field| Some((field, query.strip_prefix(field)?)))?;
+            let (value, rest) = parse_quoted(rest)?;
+            filters.push(match field {
+                "name = " => Box::new(move |file| file.name == value),
+                // Drive matches the start of any word of the name.
+                "name contains " => Box::new(move |file| {
+                    let (name, value) = (file.name.to_lowercase(), value.to_lowercase());
+                    name.match_indices(&value).any(|(index, _)| {
+                        !name[..index]
+                            .chars()
+                            .next_back()
+                            .is_some_and(char::is_alphanumeric)
+                    })
+                }),
+                "mimeType = " => Box::new(move |file| file.mime_type == value),
+                "mimeType != " => Box::new(move |file| file.mime_type != value),
+                "fullText contains " => Box::new(move |file| {
+                    let value = value.to_lowercase();
+                    file.name.to_lowercase().contains(&value)
+                        || file.content.to_lowercase().contains(&value)
+                }),
+                _ => Box::new(|_| true),
+            });
+            query = rest;
+        }
+        query = query.strip_prefix(" and ").unwrap_or(query);
+    }
+    Some(filters)
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ListQuery {
+    q: Option<String>,
+    order_by: Option<String>,
+    page_size: Option<usize>,
+    page_token: Option<String>,
+}
+
+async fn list_files(req: HttpRequest, state: State, query: web::Query<ListQuery>) -> HttpResponse {
+    let drive = authorise!(req, state);
+    let Some(filters) = parse_query(query.q.as_deref().unwrap_or_default()) else {
+        return google_error(400, "INVALID_ARGUMENT", "Invalid Value");
+    };
+    let mut matching: Vec<&File> = drive
+        .files
+        .iter()
+        .filter(|file| filters.iter().all(|filter| filter(file)))
+        .collect();
+    if let Some(order_by) = &query.order_by {
+        let (key, descending) = order_by
+            .strip_suffix(" desc")
+            .map_or((order_by.as_str(), false), |key| (key, true));
+        match key {
+            "name" => matching.sort_by(|a, b| a.name.cmp(&b.name)),
+            "modifiedTime" => matching.sort_by_key(|file| file.revision),
+            "createdTime" => {}
+            _ => return google_error(400, "INVALID_ARGUMENT", "Invalid orderBy"),
+        }
+        if descending {
+            matching.reverse();
+        }
+    }
+    let start = query
+        .page_token
+        .as_deref()
+        .map_or(0, |token| token.parse().unwrap());
+    let page_size = query.page_size.unwrap_or(100);
+    if !(1..=1000).contains(&page_size) {
+        return google_error(400, "INVALID_ARGUMENT", "Invalid pageSize");
+    }
+    let end = (start + page_size).min(matching.len());
+    let mut list = json!({
+        "kind": "drive#fileList",
+        "incompleteSearch": false,
+        "files": matching[start..end].iter().map(|file| file.to_json()).collect::<Vec<_>>(),
+    });
+    if end < matching.len() {
+        list["nextPageToken"] = end.to_string().into();
+    }
+    HttpResponse::Ok().json(list)
+}
+
+async fn start_page_token(req: HttpRequest, state: State) -> HttpResponse {
+    let drive = authorise!(req, state);
+    HttpResponse::Ok().json(json!({
+        "kind": "drive#startPageToken",
+        "startPageToken": drive.changes.len().to_string(),
+    }))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ChangesQuery {
+    page_token: String,
+}
+
+/// Lists the changes from the page token, with the files as they are now.
+async fn list_changes(
+    req: HttpRequest,
+    state: State,
+    query: web::Query<ChangesQuery>,
+) -> HttpResponse {
+    let drive = authorise!(req, state);
+    let Some(changed) = query
+        .page_token
+        .parse()
+        .ok()
+        .and_then(|start: usize| drive.changes.get(start..))
+    else {
+        return google_error(400, "INVALID_ARGUMENT", "Invalid pageToken");
+    };
+    let changes: Vec<Value> = changed
+        .iter()
+        .map(|id| match drive.files.iter().find(|file| file.id == *id) {
+            Some(file) => json!({ "fileId": id, "removed": false, "file": file.to_json() }),
+            None => json!({ "fileId": id, "removed": true }),
+        })
+        .collect();
+    HttpResponse::Ok().json(json!({
+        "kind": "drive#changeList",
+        "changes": changes,
+        "newStartPageToken": drive.changes.len().to_string(),
+    }))
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct NewFile {
+    name: String,
+    mime_type: String,
+    #[serde(default)]
+    parents: Vec<String>,
+}
+
+async fn create_file(req: HttpRequest, state: State, file: web::Json<NewFile>) -> HttpResponse {
+    let mut drive = authorise!(req, state);
+    let parents = file.parents.clone();
+    HttpResponse::Ok().json(
+        drive
+            .add_file(&file.name, &file.mime_type, parents, "")
+            .to_json(),
+    )
+}
+
+/// Creates a file from a `multipart/related` upload: JSON metadata, then the
+/// optional content.
+async fn upload_file(req: HttpRequest, state: State, body: String) -> HttpResponse {
+    let mut drive = authorise!(req, state);
+    let Some(boundary) = req
+        .headers()
+        .get("Content-Type")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split_once("boundary="))
+        .map(|(_, boundary)| format!("--{boundary}"))
+    else {
+        return google_error(400, "INVALID_ARGUMENT", "Missing boundary");
+    };
+    let parts: Vec<&str> = body
+        .split(&boundary)
+        .filter_map(|part| part.strip_prefix("\r\n"))
+        .filter_map(|part| part.split_once("\r\n\r\n"))
+        .map(|(_, content)| content.strip_suffix("\r\n").unwrap_or(content))
+        .collect();
+    let Some(file) = parts
+        .first()
+        .and_then(|metadata| serde_json::from_str::<NewFile>(metadata).ok())
+    else {
+        return google_error(400, "INVALID_ARGUMENT", "Invalid metadata");
+    };
+    let content = parts.get(1).copied().unwrap_or_default();
+    HttpResponse::Ok().json(
+        drive
+            .add_file(&file.name, &file.mime_type, file.parents, content)
+            .to_json(),
+    )
+}
+
+#[derive(Deserialize)]
+struct FileQuery {
+    alt: Option<String>,
+    fields: Option<String>,
+}
+
+/// Keeps the top-level fields of a file selected in Drive's syntax, like
+/// `id,owners(displayName)`.
+fn select_fields(file: Value, fields: &str) -> Value {
+    let mut names = Vec::new();
+    let mut depth = 0;
+    let mut name = String::new();
+    for char in fields.chars().chain([',']) {
+        match char {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ',' if depth == 0 => names.push(std::mem::take(&mut name)),
+            _ if depth == 0 => name.push(char),
+            _ => {}
+        }
+    }
+    let Value::Object(file) = file else {
+        return file;
+    };
+    Value::Object(
+        file.into_iter()
+            .filter(|(key, _)| names.contains(key))
+            .collect(),
+    )
+}
+
+async fn get_file(
+    req: HttpRequest,
+    state: State,
+    id: web::Path<String>,
+    query: web::Query<FileQuery>,
+) -> HttpResponse {
+    let mut drive = authorise!(req, state);
+    let Some(file) = drive.file_mut(&id) else {
+        return google_error(404, "NOT_FOUND", &format!("File not found: {id}."));
+    };
+    match query.alt.as_deref() {
+        Some("media") if file.is_document() =>
+            google_error(403, "FORBIDDEN", "Only files with binary content can be downloaded."),
+        Some("media") => HttpResponse::Ok().body(file.content.clone()),
+        _ => HttpResponse::Ok().json(match &query.fields {
+            Some(fields) => select_fields(file.to_json(), fields),
+            None => file.to_json(),
+        }),
+    }
+}
+
+#[derive(Deserialize)]
+struct FilePatch {
+    name: Option<String>,
+    trashed: Option<bool>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ParentsQuery {
+    add_parents: Option<String>,
+    remove_parents: Option<String>,
+}
+
+async fn patch_file(
+    req: HttpRequest,
+    state: State,
+    id: web::Path<String>,
+    query: web::Query<ParentsQuery>,
+    patch: web::Json<FilePatch>,
+) -> HttpResponse {
+    let mut drive = authorise!(req, state);
+    let Some(file) = drive.file_mut(&id) else {
+        return google_error(404, "NOT_FOUND", &format!("File not found: {id}."));
+    };
+    if let Some(name) = &patch.name {
+        name.clone_into(&mut file.name);
+    }
+    if let Some(trashed) = patch.trashed {
+        file.trashed = trashed;
+    }
+    if let Some(removed) = &query.remove_parents {
+        let removed: Vec<&str> = removed.split(',').collect();
+        file.parents
+            .retain(|parent| !removed.contains(&parent.as_str()));
+    }
+    if let Some(added) = &query.add_parents {
+        file.parents.extend(added.split(',').map(str::to_owned));
+    }
+    let response = file.to_json();
+    drive.changes.push(id.into_inner());
+    HttpResponse::Ok().json(response)
+}
+
+async fn delete_file(req: HttpRequest, state: State, id: web::Path<String>) -> HttpResponse {
+    let mut drive = authorise!(req, state);
+    let count = drive.files.len();
+    drive.files.retain(|file| file.id != *id);
+    if drive.files.len() == count {
+        google_error(404, "NOT_FOUND", &format!("File not found: {id}."))
+    } else {
+        drive.changes.push(id.into_inner());
+        HttpResponse::NoContent().finish()
+    }
+}
+
+async fn export_file(req: HttpRequest, state: State, id: web::Path<String>) -> HttpResponse {
+    let mut drive = authorise!(req, state);
+    match drive.file_mut(&id) {
+        Some(file) if file.is_document() => HttpResponse::Ok()
+            .content_type("text/plain")
+            .body(format!("\u{feff}{}", file.content.replace('\n', "\r\n"))),
+        Some(_) => google_error(403, "FORBIDDEN", "Export only supports Docs Editors files."),
+        None => google_error(404, "NOT_FOUND", &format!("File not found: {id}.")),
+    }
+}
+
+async fn update_file(
+    req: HttpRequest,
+    state: State,
+    id: web::Path<String>,
+    body: String,
+) -> HttpResponse {
+    let mut drive = authorise!(req, state);
+    let Some(file) = drive.file_mut(&id) else {
+        return google_error(404, "NOT_FOUND", &format!("File not found: {id}."));
+    };
+    file.content = body;
+    file.revision += 1;
+    let response = file.to_json();
+    drive.changes.push(id.into_inner());
+    HttpResponse::Ok().json(response)
+}
+
+async fn get_document(req: HttpRequest, state: State, id: web::Path<String>) -> HttpResponse {
+    let mut drive = authorise!(req, state);
+    match drive.file_mut(&id) {
+        Some(file) if file.is_document() => HttpResponse::Ok().json(file.to_document()),
+        _ => google_error(404, "NOT_FOUND", "Requested entity was not found."),
+    }
+}
+
+/// Applies `deleteContentRange` and `insertText` requests, with Docs' UTF-16
+/// indices and write control.
+async fn batch_update(
+    req: HttpRequest,
+    state: State,
+    id: web::Path<String>,
+    update: web::Json<Value>,
+) -> HttpResponse {
+    let mut drive = authorise!(req, state);
+    let Some(id) = id.strip_suffix(":batchUpdate") else {
+        return google_error(404, "NOT_FOUND", "Unknown method.");
+    };
+    let Some(file) = drive.file_mut(id).filter(|file| file.is_document()) else {
+        return google_error(404, "NOT_FOUND", "Requested entity was not found.");
+    };
+    if let Some(required) = update["writeControl"]["requiredRevisionId"].as_str()
+        && required != file.revision_id()
+    {
+        return google_error(
+            400,
+            "FAILED_PRECONDITION",
+            "The required revision ID does not match the latest revision.",
+        );
+    }
+    let mut body: Vec<u16> = format!("{}\n", file.content).encode_utf16().collect();
+    for request in update["requests"].as_array().unwrap() {
+        if let Some(range) = request.get("deleteContentRange") {
+            let start = range["range"]["startIndex"].as_u64().unwrap() as usize;
+            let end = range["range"]["endIndex"].as_u64().unwrap() as usize;
+            if start < 1 || end > body.len() || start >= end {
+                return google_error(400, "INVALID_ARGUMENT", "Invalid deletion range.");
+            }
+            body.drain(start - 1..end - 1);
+        } else if let Some(insert) = request.get("insertText") {
+            let index = insert["location"]["index"].as_u64().unwrap() as usize;
+            if index < 1 || index > body.len() {
+                return google_error(400, "INVALID_ARGUMENT", "Invalid insertion index.");
+            }
+            let text: Vec<u16> = insert["text"].as_str().unwrap().encode_utf16().collect();
+            body.splice(index - 1..index - 1, text);
+        } else {
+            return google_error(400, "INVALID_ARGUMENT", "Unsupported request.");
+        }
+    }
+    let mut text = String::from_utf16(&body).unwrap();
+    text.pop();
+    file.content = text;
+    file.revision += 1;
+    let response = json!({
+        "documentId": id,
+        "replies": [],
+        "writeControl": { "requiredRevisionId": file.revision_id() },
+    });
+    drive.batch_updates.push(update.into_inner());
+    drive.changes.push(id.to_owned());
+    HttpResponse::Ok().json(response)
+}
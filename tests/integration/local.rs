@@ -0,0 +1,176 @@
+use std::fs;
+
+use actix_http::Request;
+use actix_web::Error;
+use actix_web::body::MessageBody;
+use actix_web::dev::{Service, ServiceResponse};
+use actix_web::http::StatusCode;
+use actix_web::http::header::{ETAG, HeaderMap, IF_MATCH};
+use actix_web::test::{self, TestRequest};
+use serde_json::Value;
+
+use crate::{init_local_app, temp_dir};
+
+/// Sends a request without session, returning the status, the headers and
+/// the body.
+async fn send<S, B>(app: &S, request: TestRequest) -> (StatusCode, HeaderMap, String)
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = Error>,
+    B: MessageBody,
+{
+    let response = test::call_service(app, request.to_request()).await;
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = test::read_body(response).await;
+    (status, headers, String::from_utf8(body.to_vec()).unwrap())
+}
+
+#[actix_web::test]
+async fn notes_are_created_saved_and_listed_without_login() {
+    let dir = temp_dir("create");
+    let app = init_local_app(dir.clone()).await;
+
+    let (status, _, id) = send(&app, TestRequest::get().uri("/drive/action/create/todo")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(id, "todo.md");
+    let (status, _, _) = send(
+        &app,
+        TestRequest::post()
+            .uri("/drive/action/set-content/todo.md")
+            .set_payload("# Todo\n"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+
+    assert_eq!(fs::read_to_string(dir.join("todo.md")).unwrap(), "# Todo\n");
+    let (_, headers, content) =
+        send(&app, TestRequest::get().uri("/drive/action/get-content/todo.md")).await;
+    assert_eq!(content, "# Todo\n");
+    assert!(headers.contains_key(ETAG));
+    let (_, _, listing) = send(&app, TestRequest::get().uri("/drive/ls")).await;
+    let listing: Value = serde_json::from_str(&listing).unwrap();
+    assert_eq!(listing["files"][0]["id"], "todo.md");
+}
+
+#[actix_web::test]
+async fn stale_saves_conflict() {
+    let dir = temp_dir("conflict");
+    fs::write(dir.join("note.md"), "first").unwrap();
+    let app = init_local_app(dir.clone()).await;
+    let (_, headers, _) =
+        send(&app, TestRequest::get().uri("/drive/action/get-content/note.md")).await;
+    let etag = headers.get(ETAG).unwrap().clone();
+    fs::write(dir.join("note.md"), "edited elsewhere").unwrap();
+
+    let (status, _, body) = send(
+        &app,
+        TestRequest::post()
+            .uri("/drive/action/set-content/note.md")
+            .insert_header((IF_MATCH, etag))
+            .set_payload("second"),
+    )
+    .await;
+
+    assert_eq!(status, StatusCode::CONFLICT);
+    let body: Value = serde_json::from_str(&body).unwrap();
+    assert_eq!(body["error"]["content"], "edited elsewhere");
+    assert_eq!(fs::read_to_string(dir.join("note.md")).unwrap(), "edited elsewhere");
+}
+
+#[actix_web::test]
+async fn notes_are_renamed_and_deleted() {
+    let dir = temp_dir("rename");
+    fs::write(dir.join("old.md"), "content").unwrap();
+    let app = init_local_app(dir.clone()).await;
+
+    let (status, _, metadata) = send(
+        &app,
+        TestRequest::post()
+            .uri("/drive/action/rename/old.md")
+            .set_payload("new"),
+    )
+    .await;
+    assert_eq!(status, StatusCode::OK);
+    let metadata: Value = serde_json::from_str(&metadata).unwrap();
+    assert_eq!(metadata["id"], "new.md");
+    let (status, _, _) = send(&app, TestRequest::post().uri("/drive/action/delete/new.md")).await;
+    assert_eq!(status, StatusCode::OK);
+
+    assert!(fs::read_dir(&dir).unwrap().next().is_none());
+}
+
+#[actix_web::test]
+async fn ids_outside_the_directory_are_rejected() {
+    let app = init_local_app(temp_dir("escape")).await;
+
+    let (status, _, _) =
+        send(&app, TestRequest::get().uri("/drive/action/get-content/..%2Fsecret.md")).await;
+    let (hidden, _, _) =
+        send(&app, TestRequest::get().uri("/drive/action/get-content/.hidden.md")).await;
+
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+    assert_eq!(hidden, StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn trashed_notes_are_listed_apart_and_restored() {
+    let dir = temp_dir("trash");
+    fs::write(dir.join("note.md"), "content").unwrap();
+    let app = init_local_app(dir.clone()).await;
+
+    let (status, _, _) = send(&app, TestRequest::delete().uri("/drive/action/note.md")).await;
+    let (_, _, listed) = send(&app, TestRequest::get().uri("/drive/ls")).await;
+    let (_, _, trashed) = send(&app, TestRequest::get().uri("/drive/trash")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(serde_json::from_str::<Value>(&listed).unwrap()["files"], Value::Array(vec![]));
+    assert_eq!(serde_json::from_str::<Value>(&trashed).unwrap()["files"][0]["id"], "note.md");
+
+    let (status, _, _) = send(&app, TestRequest::post().uri("/drive/action/note.md/restore")).await;
+    assert_eq!(status, StatusCode::OK);
+    assert_eq!(fs::read_to_string(dir.join("note.md")).unwrap(), "content");
+}
+
+#[actix_web::test]
+async fn listings_are_sorted_and_filtered_by_name() {
+    let dir = temp_dir("order");
+    for name in ["Apex.md", "apple.md", "apricot.md", "banana.md"] {
+        fs::write(dir.join(name), "").unwrap();
+    }
+    let app = init_local_app(dir).await;
+
+    let (_, _, listing) =
+        send(&app, TestRequest::get().uri("/drive/ls?name_prefix=aP&orderBy=name%20desc")).await;
+    let (status, _, _) = send(&app, TestRequest::get().uri("/drive/ls?orderBy=modifiedTime")).await;
+
+    let listing: Value = serde_json::from_str(&listing).unwrap();
+    let ids: Vec<_> = listing["files"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .map(|file| &file["id"])
+        .collect();
+    assert_eq!(ids, ["apricot.md", "apple.md", "Apex.md"]);
+    assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[actix_web::test]
+async fn rendered_links_cant_run_scripts() {
+    let dir = temp_dir("links");
+    fs::write(
+        dir.join("links.md"),
+        "[site](https://example.com) [mail](mailto:a@example.com) [up](../other.md)\n\
+         [x](javascript:alert(1)) [y](JavaScript&#x09;:alert(2)) ![img](data:text/html,hi)\n",
+    )
+    .unwrap();
+    let app = init_local_app(dir).await;
+
+    let (status, _, page) = send(&app, TestRequest::get().uri("/view/links.md")).await;
+
+    assert_eq!(status, StatusCode::OK);
+    assert!(page.contains("href=\"https://example.com\""));
+    assert!(page.contains("href=\"mailto:a@example.com\""));
+    assert!(page.contains("href=\"../other.md\""));
+    assert_eq!(page.matches("href=\"#\"").count(), 2);
+    assert!(page.contains("src=\"#\""));
+    assert!(!page.to_lowercase().contains("script:"));
+}
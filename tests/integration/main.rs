@@ -0,0 +1,167 @@
+//! Integration tests driving the server's routes against a fake Google.
+
+mod auth;
+mod drive;
+mod fake_google;
+mod local;
+
+use std::path::PathBuf;
+use std::time::Duration;
+
+use actix_http::Request;
+use actix_web::body::MessageBody;
+use actix_web::cookie::Cookie;
+use actix_web::dev::{Service, ServiceResponse};
+use actix_web::http::StatusCode;
+use actix_web::http::header::LOCATION;
+use actix_web::test::{self, TestRequest};
+use actix_web::{App, Error};
+use fake_google::{FakeGoogle, GOOD_CODE};
+use md_viewer::{
+    AppState, Env, GoogleAuthCredentials, GoogleUrls, HttpConfig, NoteFormat, RetryPolicy, StorageConfig, config
+};
+
+const APP_FOLDER: &str = "md-viewer-tests";
+
+/// Settings of the server, talking to Google at the given URLs.
+fn env(google_urls: GoogleUrls, note_format: NoteFormat, storage: StorageConfig) -> Env {
+    Env {
+        credentials: GoogleAuthCredentials::new(
+            "client-id".to_owned(),
+            "http://localhost:8080/auth/callback/google".to_owned(),
+            "client-secret".to_owned(),
+        ),
+        addr: ("127.0.0.1".to_owned(), 8080),
+        app_folder: APP_FOLDER.to_owned(),
+        note_format,
+        google_urls,
+        storage,
+        http: HttpConfig {
+            retry: RetryPolicy {
+                max_attempts: 3,
+                base_delay: Duration::from_millis(1),
+                max_delay: Duration::from_millis(10),
+            },
+            ..HttpConfig::default()
+        },
+        search_index: None,
+        sync_interval: None,
+    }
+}
+
+/// Empty directory for the files of one test.
+pub fn temp_dir(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("md-viewer-{}-{test}", std::process::id()));
+    drop(std::fs::remove_dir_all(&dir));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+async fn init_with(
+    env: Env,
+) -> impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = Error> {
+    test::init_service(
+        App::new()
+            .configure(config)
+            .app_data(AppState::new(env).unwrap()),
+    )
+    .await
+}
+
+/// Server storing the notes in the fake Google's Drive.
+pub async fn init_app(
+    fake: &FakeGoogle,
+    note_format: NoteFormat,
+) -> impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = Error> {
+    init_with(env(fake.urls(), note_format, StorageConfig::Drive)).await
+}
+
+/// Server storing the notes in the fake Google's Drive, and searching them in
+/// a local index.
+pub async fn init_indexed_app(
+    fake: &FakeGoogle,
+    index_dir: PathBuf,
+) -> impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = Error> {
+    init_with(Env {
+        search_index: Some(index_dir),
+        ..env(fake.urls(), NoteFormat::Markdown, StorageConfig::Drive)
+    })
+    .await
+}
+
+/// Server storing the notes in the fake Google's Drive, and syncing their
+/// metadata from the changes feed at the interval.
+pub async fn init_synced_app(
+    fake: &FakeGoogle,
+    interval: Duration,
+) -> impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = Error> {
+    init_with(Env {
+        sync_interval: Some(interval),
+        ..env(fake.urls(), NoteFormat::Document, StorageConfig::Drive)
+    })
+    .await
+}
+
+/// Server storing the notes in a local directory, without Google.
+pub async fn init_local_app(
+    root: PathBuf,
+) -> impl Service<Request, Response = ServiceResponse<impl MessageBody>, Error = Error> {
+    init_with(env(GoogleUrls::default(), NoteFormat::Markdown, StorageConfig::Local(root))).await
+}
+
+pub fn location<B>(response: &ServiceResponse<B>) -> &str {
+    response.headers().get(LOCATION).unwrap().to_str().unwrap()
+}
+
+pub fn session_cookie<B>(response: &ServiceResponse<B>) -> Cookie<'static> {
+    response
+        .response()
+        .cookies()
+        .find(|cookie| cookie.name() == "md-viewer-session")
+        .unwrap()
+        .into_owned()
+}
+
+/// Goes through the login flow, returning the session cookie of the logged in
+/// browser.
+pub async fn log_in<S, B>(app: &S) -> Cookie<'static>
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = Error>,
+    B: MessageBody,
+{
+    let login = test::call_service(app, TestRequest::get().uri("/auth/login").to_request()).await;
+    assert_eq!(login.status(), StatusCode::FOUND);
+    let state = location(&login)
+        .split('&')
+        .find_map(|param| param.strip_prefix("state="))
+        .unwrap()
+        .to_owned();
+    let callback = test::call_service(
+        app,
+        TestRequest::get()
+            .uri(&format!("/auth/callback/google?code={GOOD_CODE}&state={state}"))
+            .cookie(session_cookie(&login))
+            .to_request(),
+    )
+    .await;
+    assert_eq!(callback.status(), StatusCode::FOUND);
+    session_cookie(&callback)
+}
+
+/// Sends a request as the logged in browser, returning the status, the
+/// headers and the body.
+pub async fn call<S, B>(
+    app: &S,
+    cookie: &Cookie<'static>,
+    request: TestRequest,
+) -> (StatusCode, actix_web::http::header::HeaderMap, String)
+where
+    S: Service<Request, Response = ServiceResponse<B>, Error = Error>,
+    B: MessageBody,
+{
+    let response = test::call_service(app, request.cookie(cookie.clone()).to_request()).await;
+    let status = response.status();
+    let headers = response.headers().clone();
+    let body = test::read_body(response).await;
+    (status, headers, String::from_utf8(body.to_vec()).unwrap())
+}